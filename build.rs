@@ -1,19 +1,42 @@
 use fs::File;
 use io::{BufRead, Read, Write};
 use path::{Path, PathBuf};
+use std::cmp::Ordering;
 use std::env::var_os;
-use std::{env, fmt, fs, io, path};
+use std::process::{Command, Stdio};
+use std::{env, ffi, fmt, fs, io, path};
 
 enum Error {
     GitDirNotFound,
+    BrokenGitDirLink(PathBuf),
+    GitDirFileBroken(PathBuf, PathBuf),
+    UnrelatedGitDir(PathBuf),
     Io(io::Error),
     OutDir(env::VarError),
     InvalidUserHooksDir(PathBuf),
     EmptyUserHook(PathBuf),
+    InvalidHookDirective(PathBuf, String),
+    HookDirNotWritable(PathBuf),
+    HookCommandNotSet(&'static str, &'static str),
+    InvalidConfig(PathBuf, String),
+    GitConfigFailed(String),
+    WorktreeHooksFailed(String),
+    InvalidHookScript(&'static str, String),
+    // Errors of hooks which failed to be installed, and whether other hooks were installed
+    HooksFailed(Vec<(String, Error)>, bool),
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+// Diagnostics shown in the output of cargo when $CARGO_HUSKY_VERBOSE is set
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if var_os("CARGO_HUSKY_VERBOSE").is_some() {
+            println!("cargo:warning={}", format!($($arg)*));
+        }
+    };
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
@@ -30,298 +53,3897 @@ impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match self {
             Error::GitDirNotFound => format!(
-                ".git directory was not found in '{}' or its parent directories",
+                ".git directory was not found in '{}', '{}' or their parent directories",
+                env::var("OUT_DIR").unwrap_or_else(|_| "".to_string()),
+                env!("CARGO_MANIFEST_DIR"),
+            ),
+            Error::BrokenGitDirLink(path) => format!(
+                "{:?} is a symbolic link to a directory which does not exist. No hook was installed",
+                path,
+            ),
+            Error::GitDirFileBroken(file, target) => format!(
+                "{:?} file points to {:?} which is not a directory. The worktree or submodule may have been moved or removed. No hook was installed",
+                file, target,
+            ),
+            Error::UnrelatedGitDir(gitdir) => format!(
+                "{:?} was found from the directory of cargo-husky since .git was not found from '{}', but it is not the repository of the crate depending on cargo-husky (cargo-husky's own repository or no Cargo.toml at its root). No hook was installed",
+                gitdir,
                 env::var("OUT_DIR").unwrap_or_else(|_| "".to_string()),
             ),
             Error::Io(inner) => format!("IO error: {}", inner),
-            Error::OutDir(env::VarError::NotPresent) => unreachable!(),
-            Error::OutDir(env::VarError::NotUnicode(msg)) => msg.to_string_lossy().to_string(),
+            Error::OutDir(env::VarError::NotPresent) => "$OUT_DIR is not set. The build script must be run by cargo. No hook was installed".to_string(),
+            Error::OutDir(env::VarError::NotUnicode(msg)) => format!(
+                "$OUT_DIR {:?} is not valid Unicode. No hook was installed",
+                msg,
+            ),
             Error::InvalidUserHooksDir(path) => {
                 format!("User hooks directory is not found or no executable file is found in '{:?}'. Did you forget to make a hook script executable?", path)
-	        }
+            }
             Error::EmptyUserHook(path) => format!("User hook script is empty: {:?}", path),
+            Error::InvalidHookDirective(path, directive) => format!(
+                "Invalid directive {:?} in user hook {:?}. 'hook=<name>' with a Git hook name such as 'pre-push' is expected",
+                directive, path,
+            ),
+            Error::HookDirNotWritable(path) => format!(
+                "Hooks directory {:?} is not writable. No hook was installed",
+                path,
+            ),
+            Error::HookCommandNotSet(hook, var) => format!(
+                "Command to run in {} hook is not set. Please set it to '${}' environment variable",
+                hook, var,
+            ),
+            Error::InvalidConfig(path, msg) => {
+                format!("Invalid cargo-husky configuration in {:?}: {}", path, msg)
+            }
+            Error::GitConfigFailed(stderr) => format!(
+                "Failed to set 'core.hooksPath' with `git config`. No hook was installed: {}",
+                stderr.trim(),
+            ),
+            Error::WorktreeHooksFailed(stderr) => format!(
+                "Failed to install hooks to worktrees by `git`: {}",
+                stderr.trim(),
+            ),
+            Error::InvalidHookScript(checker, output) => format!(
+                "Generated hook script was rejected by `{}` as POSIX sh. Please check the configured commands or report this issue with the output: {}",
+                checker,
+                output.trim(),
+            ),
+            Error::HooksFailed(errors, _) => {
+                let mut msg = format!("Failed to install {} hook(s):", errors.len());
+                for (hook, err) in errors {
+                    msg.push_str(&format!("\n  {}: {:?}", hook, err));
+                }
+                msg
+            }
         };
         write!(f, "{}", msg)
     }
 }
 
+impl Error {
+    // Errors which should not break the build of the crate depending on cargo-husky (#2)
+    fn is_warning(&self) -> bool {
+        match self {
+            Error::GitDirNotFound
+            | Error::BrokenGitDirLink(..)
+            | Error::GitDirFileBroken(..)
+            | Error::UnrelatedGitDir(..)
+            | Error::HookDirNotWritable(..)
+            | Error::GitConfigFailed(..)
+            | Error::OutDir(..) => true,
+            Error::HooksFailed(errors, _) => errors.iter().all(|(_, e)| e.is_warning()),
+            _ => false,
+        }
+    }
+}
+
+// Canonicalize the git directory so that hooks are put in the real location even if .git is a
+// symbolic link
 fn resolve_gitdir() -> Result<PathBuf> {
-    let dir = env::var("OUT_DIR")?;
-    let mut dir = PathBuf::from(dir);
-    if !dir.has_root() {
-        dir = fs::canonicalize(dir)?;
+    Ok(fs::canonicalize(find_gitdir()?)?)
+}
+
+fn find_gitdir() -> Result<PathBuf> {
+    // Git sets $GIT_DIR and respects it. Relative path is ignored since the working directory of
+    // cargo is unknown in build script
+    if let Some(gitdir) = var_os("GIT_DIR").map(PathBuf::from) {
+        if gitdir.is_absolute() && gitdir.is_dir() {
+            return Ok(common_gitdir(gitdir));
+        }
+        verbose!(
+            "Ignored $GIT_DIR {:?} since it is not an absolute path to a directory",
+            gitdir,
+        );
+    }
+
+    // OUT_DIR may be outside the repository when target directory is customized (e.g. on another
+    // drive on Windows). Then try the directory of cargo-husky itself, which is in the repository
+    // when it is vendored or a path dependency. Packages downloaded in $CARGO_HOME are not related
+    // to the repository
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let is_downloaded = var_os("CARGO_HOME")
+        .map(|home| manifest_dir.starts_with(home))
+        .unwrap_or(false);
+    let fallback = if is_downloaded {
+        None
+    } else {
+        Some(manifest_dir.as_path())
+    };
+    find_gitdir_in(&out_dir()?, fallback)
+}
+
+// Find the git directory from the start directory, then from the fallback directory. This does not
+// read any environment variable so that the lookup can be tested with temporary directories
+fn find_gitdir_in(start: &Path, fallback: Option<&Path>) -> Result<PathBuf> {
+    if let Some(gitdir) = find_gitdir_from(start.to_owned())? {
+        return Ok(gitdir);
     }
+    if let Some(dir) = fallback {
+        if let Some(gitdir) = find_gitdir_from(dir.to_owned())? {
+            // When cargo-husky is a path dependency outside the repository, the repository found here
+            // is cargo-husky's own one. The manifest must be at the root so that configuration is
+            // read from the same repository as hooks are installed into
+            let root = dir.ancestors().find(|d| d.join(".git").exists());
+            let is_related = match root {
+                Some(root) => root != dir && root.join("Cargo.toml").is_file(),
+                None => false,
+            };
+            if !is_related {
+                return Err(Error::UnrelatedGitDir(gitdir));
+            }
+            verbose!(
+                "Found git directory {} from the manifest directory since it was not found from OUT_DIR",
+                gitdir.display(),
+            );
+            return Ok(gitdir);
+        }
+    }
+    Err(Error::GitDirNotFound)
+}
+
+// Relative OUT_DIR is resolved from the current directory
+fn out_dir() -> Result<PathBuf> {
+    let dir = PathBuf::from(env::var("OUT_DIR")?);
+    if dir.has_root() {
+        Ok(dir)
+    } else {
+        Ok(fs::canonicalize(dir)?)
+    }
+}
+
+// Walk up from the directory to find the git directory. `None` means it was not found
+fn find_gitdir_from(mut dir: PathBuf) -> Result<Option<PathBuf>> {
     loop {
         let gitdir = dir.join(".git");
+        let is_symlink = fs::symlink_metadata(&gitdir)
+            .map(|md| md.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_symlink && !gitdir.exists() {
+            return Err(Error::BrokenGitDirLink(gitdir));
+        }
         if gitdir.is_dir() {
-            return Ok(gitdir);
+            return Ok(Some(gitdir));
         }
         if gitdir.is_file() {
             let mut buf = String::new();
-            File::open(gitdir)?.read_to_string(&mut buf)?;
+            File::open(&gitdir)?.read_to_string(&mut buf)?;
+            // Git writes `gitdir: <path>`. Relative path is relative to the directory of .git file
+            // (e.g. submodules)
             let newlines: &[_] = &['\n', '\r'];
-            let gitdir = PathBuf::from(buf.trim_end_matches(newlines));
-            if !gitdir.is_dir() {
+            let buf = buf.trim_end_matches(newlines).trim();
+            let path = buf.trim_start_matches("gitdir:").trim();
+            if path.is_empty() {
                 return Err(Error::GitDirNotFound);
             }
-            return Ok(gitdir);
+            let target = dir.join(path);
+            if !target.is_dir() {
+                return Err(Error::GitDirFileBroken(gitdir, target));
+            }
+            return Ok(Some(common_gitdir(target)));
+        }
+        // In a bare repository, the directory itself is the git directory
+        if dir.join("HEAD").is_file() && dir.join("hooks").is_dir() {
+            return Ok(Some(dir));
         }
         if !dir.pop() {
-            return Err(Error::GitDirNotFound);
+            return Ok(None);
         }
     }
 }
 
-// This function returns true when
-//   - the hook was generated by the same version of cargo-husky
-//   - someone else had already put another hook script
-// For safety, cargo-husky does nothing on case2 also.
-fn hook_already_exists(hook: &Path) -> bool {
-    let f = match File::open(hook) {
-        Ok(f) => f,
-        Err(..) => return false,
-    };
+// .git file of a linked worktree points to $GIT_COMMON_DIR/worktrees/{name}. Git reads hooks from
+// the common directory, not from the worktree-specific directory.
+fn common_gitdir(gitdir: PathBuf) -> PathBuf {
+    if gitdir.parent().and_then(Path::file_name) == Some(ffi::OsStr::new("worktrees")) {
+        if let Some(common) = gitdir.parent().and_then(Path::parent) {
+            return common.to_owned();
+        }
+    }
+    gitdir
+}
 
-    let ver_line = match io::BufReader::new(f).lines().nth(2) {
-        None => return true, // Less than 2 lines. The hook script seemed to be generated by someone else
-        Some(Err(..)) => return false, // Failed to read entry. Re-generate anyway
-        Some(Ok(line)) => line,
-    };
+// .git file of a submodule points to $SUPERPROJECT_GITDIR/modules/{name}. {name} may contain '/' and
+// submodules may be nested. Hooks are put in the submodule's own hooks directory
+fn is_submodule_gitdir(gitdir: &Path) -> bool {
+    gitdir.ancestors().skip(1).any(|dir| {
+        dir.file_name() == Some(ffi::OsStr::new("modules"))
+            && dir
+                .parent()
+                .map(|p| p.join("HEAD").is_file())
+                .unwrap_or(false)
+    })
+}
 
-    if !ver_line.contains("This hook was set by cargo-husky") {
-        // The hook script was generated by someone else.
-        true
-    } else {
-        let ver_comment = format!(
-            "This hook was set by cargo-husky v{}",
-            env!("CARGO_PKG_VERSION")
-        );
-        ver_line.contains(&ver_comment)
+// Installing hooks in a submodule is valid, but they may not be what the user expects
+fn warn_submodule() {
+    if let Ok(gitdir) = resolve_gitdir() {
+        if is_submodule_gitdir(&gitdir) {
+            eprintln!(
+                "Warning: Hooks were installed in {:?} since the crate is in a Git submodule. They are run only for Git operations within the submodule, not within the superproject",
+                gitdir,
+            );
+        }
     }
 }
 
-fn write_script<W: io::Write>(w: &mut W) -> Result<()> {
-    macro_rules! raw_cmd {
-        ($c:expr) => {
-            concat!("\necho '+", $c, "'\n", $c)
-        };
+// Git reads hooks from the directory configured by `core.hooksPath` instead of .git/hooks when it is set
+fn configured_hooks_path(gitdir: &Path) -> Option<PathBuf> {
+    let out = Command::new("git")
+        .arg("--git-dir")
+        .arg(gitdir)
+        .args(["config", "--path", "--get", "core.hooksPath"])
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None; // Not configured
     }
-
-    #[cfg(feature = "run-for-all")]
-    macro_rules! cmd {
-        ($c:expr) => {
-            raw_cmd!(concat!($c, " --all"))
-        };
-        ($c:expr, $subflags:expr) => {
-            raw_cmd!(concat!($c, " --all -- ", $subflags))
-        };
+    let path = String::from_utf8(out.stdout).ok()?;
+    let path = path.trim();
+    if path.is_empty() {
+        return None;
     }
+    Some(PathBuf::from(path))
+}
 
-    #[cfg(not(feature = "run-for-all"))]
-    macro_rules! cmd {
-        ($c:expr) => {
-            raw_cmd!($c)
-        };
-        ($c:expr, $subflags:expr) => {
-            raw_cmd!(concat!($c, " -- ", $subflags))
-        };
-    }
+// Directory to put hooks with githooks-dir feature. It is activated by `core.hooksPath` and can be
+// committed to the repository so that the whole team shares the hooks
+const GITHOOKS_DIR: &str = ".githooks";
 
-    let script = {
-        let mut s = String::new();
-        if cfg!(feature = "run-cargo-fmt") {
-            s += cmd!("cargo fmt", "--check");
-        }
-        if cfg!(feature = "run-cargo-check") {
-            s += cmd!("cargo check");
+// With githooks-dir feature, set `core.hooksPath` to .githooks directory in the root of repository.
+// When it is already set to another directory, it is not overwritten and hooks are put there
+fn setup_githooks_dir() -> Result<()> {
+    if !cfg!(feature = "githooks-dir") {
+        return Ok(());
+    }
+    let gitdir = resolve_gitdir()?;
+    let root = repo_root()?;
+    let githooks = root.join(GITHOOKS_DIR);
+    if let Some(configured) = configured_hooks_path(&gitdir) {
+        let configured = root.join(configured);
+        let is_githooks = configured == githooks
+            || match (fs::canonicalize(&configured), fs::canonicalize(&githooks)) {
+                (Ok(configured), Ok(githooks)) => configured == githooks,
+                _ => false,
+            };
+        if !is_githooks {
+            eprintln!(
+                "Warning: 'core.hooksPath' is already set to {:?}. Hooks are put in it instead of {:?}",
+                configured, githooks,
+            );
+            return Ok(());
         }
-        if cfg!(feature = "run-cargo-clippy") {
-            s += cmd!("cargo clippy", "-D warnings");
+    } else {
+        // Relative path is resolved from the root of working tree by Git. It works in other clones
+        let out = Command::new("git")
+            .arg("--git-dir")
+            .arg(&gitdir)
+            .args(["config", "core.hooksPath", GITHOOKS_DIR])
+            .output()?;
+        if !out.status.success() {
+            return Err(Error::GitConfigFailed(
+                String::from_utf8_lossy(&out.stderr).into_owned(),
+            ));
         }
-        if cfg!(feature = "run-cargo-test") {
-            s += cmd!("cargo test");
+        verbose!("Set 'core.hooksPath' to {}", GITHOOKS_DIR);
+    }
+    fs::create_dir_all(&githooks)?;
+    Ok(())
+}
+
+// With worktree-hooks feature, installed hooks are also copied to the git directory of each linked
+// worktree ($GIT_COMMON_DIR/worktrees/{name}/hooks) and the worktree is configured to use them by
+// per-worktree 'core.hooksPath', which requires 'extensions.worktreeConfig'. Then each worktree can
+// have its own hooks. The main worktree keeps using the common hooks directory
+fn install_worktree_hooks() -> Result<()> {
+    if !cfg!(feature = "worktree-hooks") {
+        return Ok(());
+    }
+    let gitdir = resolve_gitdir()?;
+    let src_dir = resolve_hooks_dir()?;
+    let git = |gitdir: &Path, args: &[&str]| -> Result<String> {
+        let out = Command::new("git")
+            .arg("--git-dir")
+            .arg(gitdir)
+            .args(args)
+            .output()?;
+        if !out.status.success() {
+            return Err(Error::WorktreeHooksFailed(
+                String::from_utf8_lossy(&out.stderr).into_owned(),
+            ));
         }
-        s
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
     };
 
-    writeln!(
-        w,
-        r#"#!/bin/sh
-#
-# This hook was set by cargo-husky v{}: {}
-# Generated by script {}{}build.rs
-# Output at {}
-#
+    // The first entry is the main worktree
+    let worktrees = git(&gitdir, &["worktree", "list", "--porcelain"])?
+        .lines()
+        .filter_map(|l| l.strip_prefix("worktree "))
+        .skip(1)
+        .map(PathBuf::from)
+        .filter(|wt| wt.is_dir()) // Removed worktrees which are not pruned yet
+        .collect::<Vec<_>>();
+    if worktrees.is_empty() {
+        return Ok(());
+    }
+    git(&gitdir, &["config", "extensions.worktreeConfig", "true"])?;
 
-set -e
-{}"#,
-        env!("CARGO_PKG_VERSION"),
-        env!("CARGO_PKG_HOMEPAGE"),
-        env!("CARGO_MANIFEST_DIR"),
-        path::MAIN_SEPARATOR,
-        env::var("OUT_DIR").unwrap_or_else(|_| "".to_string()),
-        script
-    )?;
+    for worktree in worktrees {
+        // $GIT_DIR is set when cargo is run in a hook. It would override the worktree
+        let out = Command::new("git")
+            .arg("-C")
+            .arg(&worktree)
+            .args(["rev-parse", "--absolute-git-dir"])
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE")
+            .output()?;
+        if !out.status.success() {
+            continue;
+        }
+        let wt_gitdir = PathBuf::from(String::from_utf8_lossy(&out.stdout).trim());
+        let hooks_dir = wt_gitdir.join("hooks");
+        fs::create_dir_all(&hooks_dir)?;
+        for hook in GIT_HOOK_NAMES {
+            let src = src_dir.join(hook);
+            let dst = hooks_dir.join(hook);
+            if !src.is_file() || !is_our_hook(&src) || hook_already_exists(&dst) {
+                continue;
+            }
+            fs::copy(&src, &dst)?; // Permissions are also copied
+            verbose!("Copied hook {} to {}", src.display(), dst.display());
+        }
+        if configured_hooks_path(&wt_gitdir).is_none()
+            || configured_hooks_path(&wt_gitdir) == configured_hooks_path(&gitdir)
+        {
+            let path = hooks_dir.to_string_lossy().into_owned();
+            git(
+                &wt_gitdir,
+                &["config", "--worktree", "core.hooksPath", &path],
+            )?;
+            verbose!(
+                "Set 'core.hooksPath' of worktree {} to {}",
+                worktree.display(),
+                path
+            );
+        }
+    }
     Ok(())
 }
 
-#[cfg(target_os = "windows")]
-fn create_executable_file(path: &Path) -> io::Result<File> {
-    File::create(path)
+fn resolve_hooks_dir() -> Result<PathBuf> {
+    let gitdir = resolve_gitdir()?;
+    let hooks_path = match configured_hooks_path(&gitdir) {
+        Some(p) => p,
+        None => return Ok(gitdir.join("hooks")),
+    };
+    // Relative path is resolved from the root of working tree. The path of .git before resolving
+    // a symbolic link is used since the link is put in the working tree
+    let hooks_dir = match find_gitdir()?.parent() {
+        Some(root) if hooks_path.is_relative() => root.join(hooks_path),
+        _ => hooks_path,
+    };
+    Ok(hooks_dir)
 }
 
-#[cfg(not(target_os = "windows"))]
-fn create_executable_file(path: &Path) -> io::Result<File> {
-    use std::os::unix::fs::OpenOptionsExt;
+// io::ErrorKind::ReadOnlyFilesystem is not used since it requires Rust 1.83. EROFS is 30 on Linux,
+// macOS and BSDs
+fn is_not_writable(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::PermissionDenied
+        || (cfg!(not(target_os = "windows")) && err.raw_os_error() == Some(30))
+}
 
-    fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .mode(0o755)
-        .open(path)
+// Hooks directory may not exist yet (e.g. partial clones or 'core.hooksPath' set to a new directory)
+fn create_hooks_dir() -> Result<PathBuf> {
+    let dir = resolve_hooks_dir()?;
+    if !dir.is_dir() {
+        fs::create_dir_all(&dir).map_err(|err| {
+            if is_not_writable(&err) {
+                Error::HookDirNotWritable(dir.clone())
+            } else {
+                Error::Io(err)
+            }
+        })?;
+        verbose!("Created hooks directory {}", dir.display());
+    }
+    Ok(dir)
 }
 
-fn install_hook(hook: &str) -> Result<()> {
-    let hook_path = {
-        let mut p = resolve_gitdir()?;
-        p.push("hooks");
-        p.push(hook);
-        p
-    };
-    if !hook_already_exists(&hook_path) {
-        let mut f = create_executable_file(&hook_path)?;
-        write_script(&mut f)?;
+// Marker comment to detect hooks generated by cargo-husky. e.g. "# cargo-husky-hook: v1.2.3"
+const HOOK_MARKER: &str = "cargo-husky-hook:";
+
+// Hooks generated by older versions have only this comment. e.g.
+// "# This hook was set by cargo-husky v1.2.3: {homepage}"
+const LEGACY_HOOK_MARKER: &str = "This hook was set by cargo-husky";
+
+// Markers are searched in first several lines so that short or edited hooks (e.g. a line added
+// before the marker) are detected
+const MARKER_SEARCH_LINES: usize = 10;
+
+fn find_marker_line(hook: &Path) -> io::Result<Option<String>> {
+    let f = File::open(hook)?;
+    let mut legacy = None;
+    for line in io::BufReader::new(f).lines().take(MARKER_SEARCH_LINES) {
+        // Hook may have CRLF line endings (e.g. edited on Windows)
+        let line = line?.trim_end_matches('\r').to_string();
+        if !line.trim_start().starts_with('#') {
+            continue;
+        }
+        if line.contains(HOOK_MARKER) {
+            return Ok(Some(line));
+        }
+        if legacy.is_none() && line.contains(LEGACY_HOOK_MARKER) {
+            legacy = Some(line);
+        }
     }
-    Ok(())
+    Ok(legacy)
 }
 
-fn install_user_hook(src: &Path, dst: &Path) -> Result<()> {
-    if hook_already_exists(dst) {
-        return Ok(());
+fn marker_comment() -> String {
+    format!("# {} v{}", HOOK_MARKER, env!("CARGO_PKG_VERSION"))
+}
+
+// This function returns true when
+//   - the hook was generated by the same version of cargo-husky
+//   - the hook was generated by a newer version of cargo-husky
+//   - someone else had already put another hook script
+// For safety, cargo-husky does nothing on case3 also. The version in the marker comment is parsed and
+// compared with ours as semver, so the hook is regenerated when cargo-husky was updated.
+fn hook_already_exists(hook: &Path) -> bool {
+    match fs::metadata(hook) {
+        Err(..) => return false,
+        Ok(ref m) if m.len() == 0 => return false, // Empty file (e.g. truncated). Re-generate anyway
+        Ok(..) => {}
     }
 
-    let mut lines = {
-        let mut vec = vec![];
-        for line in io::BufReader::new(File::open(src)?).lines() {
-            vec.push(line?);
-        }
-        vec
+    let ver_line = match find_marker_line(hook) {
+        Ok(Some(line)) => line,
+        Ok(None) => return true, // The hook script was generated by someone else
+        Err(..) => return false, // Failed to read entry. Re-generate anyway
     };
 
-    if lines.is_empty() {
-        return Err(Error::EmptyUserHook(src.to_owned()));
+    let ours = env!("CARGO_PKG_VERSION");
+    let installed = match hook_version(&ver_line) {
+        Some(v) => v,
+        None => return false, // Broken version comment. Re-generate anyway
+    };
+    match compare_versions(installed, ours) {
+        Some(Ordering::Equal) => true,
+        Some(Ordering::Less) => {
+            println!(
+                "cargo:warning=Updating {} generated by older cargo-husky v{} to v{}",
+                hook.display(),
+                installed,
+                ours,
+            );
+            false
+        }
+        Some(Ordering::Greater) => {
+            // Do not downgrade the hook generated by other project using newer cargo-husky
+            println!(
+                "cargo:warning={} was generated by newer cargo-husky v{}. It is not overwritten by v{}",
+                hook.display(),
+                installed,
+                ours,
+            );
+            true
+        }
+        None => false,
     }
+}
 
-    // Insert cargo-husky package version information as comment
-    if !lines[0].starts_with("#!") {
-        lines.insert(0, "#".to_string());
+// Extract version from the marker "# cargo-husky-hook: v1.2.3" or from the legacy comment line
+// "# This hook was set by cargo-husky v1.2.3: {homepage}"
+fn hook_version(line: &str) -> Option<&str> {
+    if let Some(idx) = line.find(HOOK_MARKER) {
+        let rest = line[idx + HOOK_MARKER.len()..].trim();
+        return rest.strip_prefix('v');
     }
-    lines.insert(1, "#".to_string());
-    lines.insert(
-        2,
-        format!(
-            "# This hook was set by cargo-husky v{}: {}",
-            env!("CARGO_PKG_VERSION"),
-            env!("CARGO_PKG_HOMEPAGE")
-        ),
-    );
+    let marker = "This hook was set by cargo-husky v";
+    let start = line.find(marker)? + marker.len();
+    let rest = &line[start..];
+    Some(rest.split(':').next().unwrap_or(rest).trim())
+}
 
-    let dst_file_path = dst.join(src.file_name().unwrap());
+// Compare semantic versions such as 1.2.3 and 1.3.0-beta.1. Build metadata is ignored. Returns None
+// when either of them is not a valid version
+fn compare_versions(lhs: &str, rhs: &str) -> Option<Ordering> {
+    fn parse(v: &str) -> Option<(Vec<u64>, Option<&str>)> {
+        let v = v.split('+').next().unwrap_or(v);
+        let (core, pre) = match v.find('-') {
+            Some(idx) => (&v[..idx], Some(&v[idx + 1..])),
+            None => (v, None),
+        };
+        let nums = core
+            .split('.')
+            .map(|n| n.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        if nums.len() != 3 {
+            return None;
+        }
+        Some((nums, pre))
+    }
 
-    let mut f = io::BufWriter::new(create_executable_file(&dst_file_path)?);
-    for line in lines {
-        writeln!(f, "{}", line)?;
+    fn compare_pre(lhs: &str, rhs: &str) -> Ordering {
+        // Numeric identifiers have lower precedence than alphanumeric ones
+        for (l, r) in lhs.split('.').zip(rhs.split('.')) {
+            let ord = match (l.parse::<u64>(), r.parse::<u64>()) {
+                (Ok(l), Ok(r)) => l.cmp(&r),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => l.cmp(r),
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        lhs.split('.').count().cmp(&rhs.split('.').count())
     }
 
-    Ok(())
+    let (lnums, lpre) = parse(lhs)?;
+    let (rnums, rpre) = parse(rhs)?;
+    let ord = lnums.cmp(&rnums).then_with(|| match (lpre, rpre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater, // 1.0.0 > 1.0.0-alpha
+        (Some(_), None) => Ordering::Less,
+        (Some(l), Some(r)) => compare_pre(l, r),
+    });
+    Some(ord)
 }
 
-#[cfg(target_os = "windows")]
-fn is_executable_file(entry: &fs::DirEntry) -> bool {
-    match entry.file_type() {
-        Ok(ft) => ft.is_file(),
+fn is_our_hook(hook: &Path) -> bool {
+    match find_marker_line(hook) {
+        Ok(found) => found.is_some(),
         Err(..) => false,
     }
 }
 
-#[cfg(not(target_os = "windows"))]
-fn is_executable_file(entry: &fs::DirEntry) -> bool {
-    use std::os::unix::fs::PermissionsExt;
+// Returns the version in the marker of the hook when it is newer than ours
+fn newer_hook_version(hook: &Path) -> Option<String> {
+    let ver_line = find_marker_line(hook).ok()??;
+    let installed = hook_version(&ver_line)?;
+    match compare_versions(installed, env!("CARGO_PKG_VERSION")) {
+        Some(Ordering::Greater) => Some(installed.to_string()),
+        _ => None,
+    }
+}
 
-    let ft = match entry.file_type() {
-        Ok(ft) => ft,
-        Err(..) => return false,
-    };
-    if !ft.is_file() {
-        return false;
+// Quote the given string as a single word for sh
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// Commands are known at build time so they are escaped here rather than in shell
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
     }
-    let md = match entry.metadata() {
-        Ok(md) => md,
-        Err(..) => return false,
-    };
-    let mode = md.permissions().mode();
-    mode & 0o555 == 0o555 // Check file is read and executable mode
+    quoted.push('"');
+    quoted
 }
 
-fn install_user_hooks() -> Result<()> {
-    let git_dir = resolve_gitdir()?;
-    let user_hooks_dir = {
-        let mut p = git_dir.clone();
-        p.pop();
-        p.push(".cargo-husky");
-        p.push("hooks");
-        p
-    };
+// Command run by hooks generated from features
+struct CargoCommand {
+    // Name to assign the command to hooks in configuration. e.g. precommit-commands = ["fmt"]
+    key: &'static str,
+    cmd: String,
+    // Binary of external subcommand which may not be installed
+    requires: Option<&'static str>,
+    // Whether the command is run by hooks without assignment
+    enabled: bool,
+    // Command which accepts files to check only staged files with staged-only feature
+    files_cmd: Option<String>,
+    // Arguments added to the command only in pre-commit hook
+    precommit_args: Option<String>,
+    // Hint shown on failure with failure-hints feature
+    hint: Option<String>,
+    // Rustup component which provides the subcommand
+    component: Option<&'static str>,
+    // Tests can be narrowed to modules of staged files with test-changed feature
+    filter_changed: bool,
+    // Package to install when the binary required by an external command differs from it
+    package: Option<&'static str>,
+}
 
-    if !user_hooks_dir.is_dir() {
-        return Err(Error::InvalidUserHooksDir(user_hooks_dir));
+impl CargoCommand {
+    fn new(key: &'static str, cmd: &str, enabled: bool) -> CargoCommand {
+        CargoCommand {
+            key,
+            cmd: with_cargo(&with_extra_args(
+                key,
+                &with_offline(key, &with_profile(key, &with_toolchain(key, cmd))),
+            )),
+            requires: None,
+            enabled,
+            files_cmd: None,
+            precommit_args: None,
+            hint: None,
+            component: None,
+            filter_changed: false,
+            package: None,
+        }
     }
 
-    let hook_paths = fs::read_dir(&user_hooks_dir)?
-        .filter_map(|e| e.ok().filter(is_executable_file).map(|e| e.path()))
-        .collect::<Vec<_>>();
+    fn external(key: &'static str, cmd: &str, bin: &'static str, enabled: bool) -> CargoCommand {
+        CargoCommand {
+            key,
+            cmd: with_cargo(&with_extra_args(key, &with_toolchain(key, cmd))),
+            requires: Some(bin),
+            enabled,
+            files_cmd: None,
+            precommit_args: None,
+            hint: None,
+            component: None,
+            filter_changed: false,
+            package: None,
+        }
+    }
 
-    if hook_paths.is_empty() {
-        return Err(Error::InvalidUserHooksDir(user_hooks_dir));
+    fn accept_files(mut self, cmd: &str) -> CargoCommand {
+        self.files_cmd = Some(with_toolchain(self.key, cmd));
+        self
     }
 
-    let hooks_dir = git_dir.join("hooks");
-    for path in hook_paths {
-        install_user_hook(&path, &hooks_dir)?;
+    fn filter_changed(mut self) -> CargoCommand {
+        self.filter_changed = true;
+        self
     }
 
-    Ok(())
+    fn precommit_args(mut self, args: Option<String>) -> CargoCommand {
+        self.precommit_args = args;
+        self
+    }
+
+    fn component(mut self, component: &'static str) -> CargoCommand {
+        self.component = Some(component);
+        self
+    }
+
+    // Fail with an install hint instead of cargo's confusing "no such command" error when the
+    // component is not installed. `--version` of the subcommand checks it without building anything
+    fn component_guard(&self, script: String) -> String {
+        let component = match self.component {
+            Some(c) => c,
+            None => return script,
+        };
+        let subcmd = format!("cargo {}", self.key);
+        let install = match toolchain(self.key) {
+            Some(t) => format!("rustup component add {} --toolchain {}", component, t),
+            None => format!("rustup component add {}", component),
+        };
+        format!(
+            "\nif ! {} --version >/dev/null 2>&1; then\n    echo {} >&2\n    cargo_husky_status=1\n    {}\nelse{}\nfi",
+            with_cargo(&with_toolchain(self.key, &subcmd)),
+            shell_quote(&format!(
+                "cargo-husky: `{}` is not installed. Install it by `{}`",
+                subcmd, install,
+            )),
+            on_failure(),
+            script.replace('\n', "\n    "),
+        )
+    }
+
+    // Arguments which are only valid for a workspace such as `--exclude <package>`
+    fn workspace_args(mut self, args: &str) -> CargoCommand {
+        self.cmd = with_args(&self.cmd, args);
+        self
+    }
+
+    fn package(mut self, package: &'static str) -> CargoCommand {
+        self.package = Some(package);
+        self
+    }
+
+    fn hint(mut self, hint: String) -> CargoCommand {
+        if cfg!(feature = "failure-hints") {
+            self.hint = Some(hint);
+        }
+        self
+    }
+
+    fn command_script(&self, cmd: &str) -> String {
+        command_script_with_retries(cmd, self.hint.as_deref(), retries_for(self.key))
+    }
+
+    fn script(&self, hook: &str) -> String {
+        if let Some(files_cmd) = &self.files_cmd {
+            if cfg!(feature = "staged-only") && hook == "pre-commit" {
+                return self.component_guard(staged_files_script(files_cmd, self.hint.as_deref()));
+            }
+        }
+        let cmd = match &self.precommit_args {
+            Some(args) if hook == "pre-commit" => with_args(&self.cmd, args),
+            _ => self.cmd.clone(),
+        };
+        if self.filter_changed && cfg!(feature = "test-changed") && hook == "pre-commit" {
+            return changed_tests_script(&cmd, self.hint.as_deref(), retries_for(self.key));
+        }
+        let bin = match self.requires {
+            Some(bin) => bin,
+            None => return self.component_guard(self.command_script(&cmd)),
+        };
+        // Skip the command rather than failing the hook when it is not installed
+        format!(
+            "\nif command -v {} >/dev/null 2>&1; then{}\nelse\n    echo {}\nfi",
+            bin,
+            self.command_script(&cmd).replace('\n', "\n    "),
+            shell_quote(&format!(
+                "{} is not installed. Skipped `{}`. Install it by `cargo install {}`",
+                bin,
+                cmd,
+                self.package.unwrap_or(bin),
+            )),
+        )
+    }
 }
 
-fn install() -> Result<()> {
-    if cfg!(feature = "user-hooks") {
-        return install_user_hooks();
+// Run the command with staged Rust files as arguments. It is skipped when no Rust file is staged
+fn staged_files_script(cmd: &str, hint: Option<&str>) -> String {
+    let staged = "git diff --cached --name-only --diff-filter=ACM";
+    format!(
+        "\nif [ -n \"$({} -- '*.rs')\" ]; then{}\nelse\n    echo {}\nfi",
+        staged,
+        command_script_with_hint(&format!("{} -z -- '*.rs' | xargs -0 {}", staged, cmd), hint)
+            .replace('\n', "\n    "),
+        shell_quote(&format!("No Rust file is staged. Skipped `{}`", cmd)),
+    )
+}
+
+// With test-changed feature, tests run by pre-commit hook are narrowed to modules of staged files by
+// passing module paths as test filters (e.g. src/foo/bar.rs -> `foo::bar::`). This is a heuristic:
+// tests of other modules depending on changed ones are not run, and filters match test names as
+// substrings. Files which cannot be mapped to a module (crate roots, binaries, integration tests,
+// build scripts, files outside src) fall back to the full test suite. Everything is still compiled
+const TEST_FILTERS_SCRIPT: &str = r#"
+cargo_husky_test_filters="$(git diff --cached --name-only -- '*.rs' | awk '
+{
+    path = "/" $0
+    i = index(path, "/src/")
+    if (i == 0) { full = 1; next }
+    mod = substr(path, i + 5)
+    if (mod == "lib.rs" || mod == "main.rs" || mod ~ /^bin\//) { full = 1; next }
+    sub(/\.rs$/, "", mod)
+    sub(/\/mod$/, "", mod)
+    if (mod !~ /^[A-Za-z_][A-Za-z0-9_\/]*$/) { full = 1; next }
+    gsub(/\//, "::", mod)
+    if (!(mod in seen)) { seen[mod] = 1; filters = filters " " mod "::" }
+}
+END { if (!full) print substr(filters, 2) }
+')"
+export cargo_husky_test_filters"#;
+
+fn changed_tests_script(cmd: &str, hint: Option<&str>, retries: u32) -> String {
+    // Filters are passed to the test harness. They contain no character special in shell
+    let filtered = if cmd.contains(" -- ") {
+        format!("{} $cargo_husky_test_filters", cmd)
+    } else {
+        format!("{} -- $cargo_husky_test_filters", cmd)
+    };
+    format!(
+        "{}
+if [ -n \"$cargo_husky_test_filters\" ]; then{}
+else{}
+fi",
+        TEST_FILTERS_SCRIPT,
+        command_script_with_retries(&filtered, hint, retries).replace('\n', "\n    "),
+        command_script_with_retries(cmd, hint, retries).replace('\n', "\n    "),
+    )
+}
+
+// rustfmt does not read edition from Cargo.toml. Edition 2015 is the default of cargo
+fn crate_edition() -> String {
+    let table = find_manifest()
+        .ok()
+        .and_then(|p| p)
+        .and_then(|p| Config::parse_manifest(&p).ok())
+        .unwrap_or_default();
+    table
+        .into_iter()
+        .find_map(|(key, value)| match value {
+            toml::Value::String(edition)
+                if key == ["package", "edition"] || key == ["workspace", "package", "edition"] =>
+            {
+                Some(edition)
+            }
+            _ => None,
+        })
+        .unwrap_or_else(|| "2015".to_string())
+}
+
+// Release build is run when $CARGO_HUSKY_BUILD_RELEASE is set. $CARGO_HUSKY_PROFILE takes precedence
+// since cargo rejects `--release` with `--profile`
+fn build_command(cmd: &str) -> String {
+    if var_os("CARGO_HUSKY_BUILD_RELEASE").is_some() && profile_for("build").is_none() {
+        format!("{} --release", cmd)
+    } else {
+        cmd.to_string()
     }
-    if cfg!(feature = "prepush-hook") {
-        install_hook("pre-push")?;
+}
+
+// Toolchain to run each command is given by $CARGO_HUSKY_{KEY}_TOOLCHAIN such as
+// $CARGO_HUSKY_FMT_TOOLCHAIN, or $CARGO_HUSKY_TOOLCHAIN for all commands
+fn toolchain(key: &str) -> Option<String> {
+    let var = format!("CARGO_HUSKY_{}_TOOLCHAIN", key.to_uppercase());
+    let (var, toolchain) = match env::var(&var) {
+        Ok(v) if !v.trim().is_empty() => (var, v),
+        _ => match env::var("CARGO_HUSKY_TOOLCHAIN") {
+            Ok(v) if !v.trim().is_empty() => ("CARGO_HUSKY_TOOLCHAIN".to_string(), v),
+            _ => return None,
+        },
+    };
+    let toolchain = toolchain.trim().trim_start_matches('+');
+    let is_valid = toolchain
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_');
+    if toolchain.is_empty() || !is_valid {
+        eprintln!(
+            "Warning: '${}' must be a toolchain name such as 'nightly' or '1.70.0' but got {:?}. It is ignored",
+            var, toolchain,
+        );
+        return None;
     }
-    if cfg!(feature = "precommit-hook") {
-        install_hook("pre-commit")?;
+    Some(toolchain.to_string())
+}
+
+// Path of cargo run by generated commands is given by $CARGO_HUSKY_CARGO (e.g. a wrapper, or an
+// absolute path when $PATH in Git hooks does not include cargo). It is applied after other
+// modifications since they look for the `cargo` token
+fn cargo_bin() -> Option<String> {
+    let value = env::var("CARGO_HUSKY_CARGO").ok()?;
+    let bin = value.trim();
+    if bin.is_empty() || bin.contains(['\n', '\r']) {
+        eprintln!(
+            "Warning: '$CARGO_HUSKY_CARGO' must be a non-empty path or name of cargo but got {:?}. `cargo` is used instead",
+            value,
+        );
+        return None;
     }
-    if cfg!(feature = "postmerge-hook") {
-        install_hook("post-merge")?;
+    if bin == "cargo" {
+        return None;
     }
-    Ok(())
+    Some(quote_arg(bin))
 }
 
-fn main() -> Result<()> {
-    if var_os("CARGO_HUSKY_DONT_INSTALL_HOOKS").is_some() {
-        eprintln!("Warning: Found '$CARGO_HUSKY_DONT_INSTALL_HOOKS' in env, not doing anything!");
-        return Ok(());
+fn with_cargo(cmd: &str) -> String {
+    let bin = match cargo_bin() {
+        Some(bin) => bin,
+        None => return cmd.to_string(),
+    };
+    // `cargo` may follow environment variables assigned inline (e.g. `RUSTDOCFLAGS=... cargo doc`)
+    let idx = if cmd.starts_with("cargo ") {
+        Some(0)
+    } else {
+        cmd.find(" cargo ").map(|i| i + 1)
+    };
+    match idx {
+        Some(idx) => format!("{}{}{}", &cmd[..idx], bin, &cmd[idx + "cargo".len()..]),
+        None => cmd.to_string(),
     }
+}
 
-    match install() {
-        Err(e @ Error::GitDirNotFound) => {
-            // #2
-            eprintln!("Warning: {:?}", e);
-            Ok(())
+// Put `+toolchain` after `cargo` or `rustfmt` so that rustup selects the toolchain
+fn with_toolchain(key: &str, cmd: &str) -> String {
+    let toolchain = match toolchain(key) {
+        Some(t) => t,
+        None => return cmd.to_string(),
+    };
+    for bin in &["cargo ", "rustfmt "] {
+        if let Some(idx) = cmd.find(bin) {
+            let idx = idx + bin.len();
+            return format!("{}+{} {}", &cmd[..idx], toolchain, &cmd[idx..]);
+        }
+    }
+    cmd.to_string()
+}
+
+// Commands which compile the crate are run with the cargo profile given by $CARGO_HUSKY_PROFILE.
+// `--profile <name>` form is always used so that custom profiles work. Commands listed in
+// $CARGO_HUSKY_PROFILE_EXCLUDE separated by commas (e.g. "test,check") are run with their default
+// profile
+fn profile_for(key: &str) -> Option<String> {
+    if !["check", "build", "test", "doctest"].contains(&key) {
+        return None;
+    }
+    let profile = env::var("CARGO_HUSKY_PROFILE").ok()?;
+    let profile = profile.trim();
+    if profile.is_empty() {
+        return None;
+    }
+    let is_valid = profile
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        eprintln!(
+            "Warning: '$CARGO_HUSKY_PROFILE' must be a profile name such as 'release' but got {:?}. It is ignored",
+            profile,
+        );
+        return None;
+    }
+    let excluded = env::var("CARGO_HUSKY_PROFILE_EXCLUDE").unwrap_or_default();
+    if excluded.split(',').map(str::trim).any(|k| k == key) {
+        return None;
+    }
+    Some(profile.to_string())
+}
+
+// Flaky commands are retried $CARGO_HUSKY_RETRIES times before failing the hook. Commands to retry are
+// listed in $CARGO_HUSKY_RETRY_COMMANDS separated by commas. Only test commands are retried by
+// default since other commands rarely fail by chance
+fn retries_for(key: &str) -> u32 {
+    let value = match env::var("CARGO_HUSKY_RETRIES") {
+        Ok(value) if !value.trim().is_empty() => value,
+        _ => return 0,
+    };
+    let retries = match value.trim().parse() {
+        Ok(retries) => retries,
+        Err(..) => {
+            eprintln!(
+                "Warning: '$CARGO_HUSKY_RETRIES' must be a number of retries such as '1' but got {:?}. It is ignored",
+                value,
+            );
+            return 0;
         }
-        otherwise => otherwise,
+    };
+    let retried = match env::var("CARGO_HUSKY_RETRY_COMMANDS") {
+        Ok(keys) => keys.split(',').map(str::trim).any(|k| k == key),
+        Err(..) => ["test", "nextest", "doctest"].contains(&key),
+    };
+    if retried {
+        retries
+    } else {
+        0
+    }
+}
+
+fn with_profile(key: &str, cmd: &str) -> String {
+    match profile_for(key) {
+        Some(profile) => with_args(cmd, &format!("--profile {}", profile)),
+        None => cmd.to_string(),
+    }
+}
+
+// With offline feature, `--offline` is added to cargo commands so that hooks don't access the network
+// to update the registry. `cargo fmt` does not accept it since it only runs rustfmt. External
+// subcommands have their own flags, which can be given by $CARGO_HUSKY_{KEY}_ARGS
+fn with_offline(key: &str, cmd: &str) -> String {
+    if cfg!(feature = "offline") && key != "fmt" {
+        with_args(cmd, "--offline")
+    } else {
+        cmd.to_string()
+    }
+}
+
+// Extra arguments for each command are given by $CARGO_HUSKY_{KEY}_ARGS such as
+// $CARGO_HUSKY_TEST_ARGS. They are put before `--` separator
+fn with_extra_args(key: &str, cmd: &str) -> String {
+    let var = format!("CARGO_HUSKY_{}_ARGS", key.to_uppercase());
+    match env::var(&var) {
+        Ok(args) => with_args(cmd, &args),
+        Err(..) => cmd.to_string(),
+    }
+}
+
+// Put whitespace-separated arguments before `--` separator of the command
+fn with_args(cmd: &str, args: &str) -> String {
+    let args = args
+        .split_whitespace()
+        .map(quote_arg)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if args.is_empty() {
+        return cmd.to_string();
+    }
+    match cmd.find(" -- ") {
+        Some(idx) => format!("{} {}{}", &cmd[..idx], args, &cmd[idx..]),
+        None => format!("{} {}", cmd, args),
+    }
+}
+
+// Quote an argument only when it contains characters which are special in shell
+fn quote_arg(arg: &str) -> String {
+    let is_safe = arg
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_=.,/:+@%".contains(c));
+    if is_safe {
+        arg.to_string()
+    } else {
+        shell_quote(arg)
+    }
+}
+
+fn cargo_commands() -> Vec<CargoCommand> {
+    #[cfg(feature = "run-for-all")]
+    macro_rules! cmd {
+        ($c:expr) => {
+            concat!($c, " --all")
+        };
+        ($c:expr, $subflags:expr) => {
+            concat!($c, " --all -- ", $subflags)
+        };
+    }
+
+    #[cfg(not(feature = "run-for-all"))]
+    macro_rules! cmd {
+        ($c:expr) => {
+            $c
+        };
+        ($c:expr, $subflags:expr) => {
+            concat!($c, " -- ", $subflags)
+        };
+    }
+
+    let excludes = exclude_args();
+
+    // When doc-tests are run by their own command, `cargo test` is narrowed to the other targets so
+    // that doc-tests are not run twice
+    let test = if cfg!(feature = "run-cargo-test-doc") {
+        concat!(cmd!("cargo test"), " --lib --bins --tests")
+    } else {
+        cmd!("cargo test")
+    };
+
+    // Commands are run in this order by default. Cheap ones are run first for fast feedback
+    vec![
+        // `cargo fmt` checks all files even if files are given. Run rustfmt directly for staged files
+        CargoCommand::new(
+            "fmt",
+            cmd!("cargo fmt", "--check"),
+            cfg!(feature = "run-cargo-fmt"),
+        )
+        .component("rustfmt")
+        .hint(format!("Run `{}` to fix formatting", cmd!("cargo fmt")))
+        .accept_files(&format!("rustfmt --check --edition {}", crate_edition())),
+        CargoCommand::new(
+            "clippy",
+            &format!("{} -- {}", cmd!("cargo clippy"), clippy_flags()),
+            cfg!(feature = "run-cargo-clippy"),
+        )
+        .workspace_args(&excludes)
+        .component("clippy")
+        .hint(format!(
+            "Run `{}` to fix some of the warnings automatically",
+            cmd!("cargo clippy --fix"),
+        )),
+        CargoCommand::new(
+            "check",
+            cmd!("cargo check"),
+            cfg!(feature = "run-cargo-check"),
+        )
+        .workspace_args(&excludes),
+        CargoCommand::new(
+            "build",
+            &build_command(cmd!("cargo build")),
+            cfg!(feature = "run-cargo-build"),
+        )
+        .workspace_args(&excludes),
+        // Environment variable assigned inline only affects this command
+        CargoCommand::new(
+            "doc",
+            concat!("RUSTDOCFLAGS=\"-D warnings\" ", cmd!("cargo doc --no-deps")),
+            cfg!(feature = "run-cargo-doc"),
+        )
+        .workspace_args(&excludes),
+        CargoCommand::new(
+            "nextest",
+            cmd!("cargo nextest run"),
+            cfg!(feature = "run-cargo-nextest"),
+        )
+        .workspace_args(&excludes),
+        // Running both would execute all tests twice. Tests run by pre-commit hook can be narrowed by
+        // $CARGO_HUSKY_TEST_FILTER (e.g. `--lib`) while other hooks run the full test suite
+        CargoCommand::new(
+            "test",
+            test,
+            cfg!(feature = "run-cargo-test") && !cfg!(feature = "run-cargo-nextest"),
+        )
+        .workspace_args(&excludes)
+        .filter_changed()
+        .precommit_args(env::var("CARGO_HUSKY_TEST_FILTER").ok()),
+        // Doc-tests are run separately so that they can be assigned to a hook other than the one
+        // running tests. It is also useful with nextest, which does not run doc-tests
+        CargoCommand::new(
+            "doctest",
+            concat!(cmd!("cargo test"), " --doc"),
+            cfg!(feature = "run-cargo-test-doc"),
+        )
+        .workspace_args(&excludes)
+        .filter_changed(),
+        // Query metadata in .sqlx directory is checked for the package. `--all` is not added since
+        // `cargo sqlx prepare` has its own `--workspace` flag with a different layout of .sqlx
+        CargoCommand::external(
+            "sqlx",
+            "cargo sqlx prepare --check",
+            "cargo-sqlx",
+            cfg!(feature = "run-sqlx-prepare-check"),
+        )
+        .package("sqlx-cli")
+        .hint("Run `cargo sqlx prepare` and commit the updated .sqlx directory".to_string()),
+        // `--all` is meaningless for auditing Cargo.lock
+        CargoCommand::external(
+            "audit",
+            "cargo audit",
+            "cargo-audit",
+            cfg!(feature = "run-cargo-audit"),
+        ),
+        // `--all` is meaningless for checking the dependency graph
+        CargoCommand::external(
+            "deny",
+            &format!("cargo deny {}", deny_subcommand()),
+            "cargo-deny",
+            cfg!(feature = "run-cargo-deny"),
+        ),
+    ]
+}
+
+// Packages excluded from workspace commands are given by $CARGO_HUSKY_EXCLUDE separated by commas
+// (e.g. generated or vendored crates). `--exclude` is only valid with `--all`
+fn exclude_args() -> String {
+    let value = match env::var("CARGO_HUSKY_EXCLUDE") {
+        Ok(value) => value,
+        Err(..) => return String::new(),
+    };
+    let packages = value
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>();
+    if packages.is_empty() {
+        return String::new();
+    }
+    if !cfg!(feature = "run-for-all") {
+        eprintln!(
+            "Warning: '$CARGO_HUSKY_EXCLUDE' is ignored since `--exclude` requires run-for-all feature"
+        );
+        return String::new();
+    }
+    let mut args = vec![];
+    for package in packages {
+        let is_valid = package
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid {
+            eprintln!(
+                "Warning: Invalid package name {:?} in '$CARGO_HUSKY_EXCLUDE' is ignored",
+                package,
+            );
+            continue;
+        }
+        args.push(format!("--exclude {}", package));
+    }
+    args.join(" ")
+}
+
+// Flags passed to clippy after `--` are given by $CARGO_HUSKY_CLIPPY_FLAGS such as
+// `-W clippy::pedantic -D warnings`. They replace the default `-D warnings`
+fn clippy_flags() -> String {
+    let default = "-D warnings".to_string();
+    let flags = match env::var("CARGO_HUSKY_CLIPPY_FLAGS") {
+        Ok(flags) => flags,
+        Err(..) => return default,
+    };
+    if flags.trim().is_empty() || flags.contains(['\n', '\r']) {
+        eprintln!(
+            "Warning: '$CARGO_HUSKY_CLIPPY_FLAGS' must be non-empty flags in a single line but got {:?}. `-D warnings` is used instead",
+            flags,
+        );
+        return default;
+    }
+    flags
+        .split_whitespace()
+        .map(quote_arg)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Subcommand of cargo-deny such as `check`, `check bans` or `check advisories`
+fn deny_subcommand() -> String {
+    let default = "check".to_string();
+    let subcmd = match env::var("CARGO_HUSKY_DENY_SUBCOMMAND") {
+        Ok(subcmd) => subcmd.trim().to_string(),
+        Err(..) => return default,
+    };
+    if subcmd.is_empty() {
+        return default;
+    }
+    let is_valid = subcmd
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == ' ');
+    if !is_valid {
+        eprintln!(
+            "Warning: '$CARGO_HUSKY_DENY_SUBCOMMAND' must be words of alphanumerics and '-' but got {:?}. `check` is used instead",
+            subcmd,
+        );
+        return default;
+    }
+    subcmd
+}
+
+fn timeout_secs() -> Option<u64> {
+    match env::var("CARGO_HUSKY_TIMEOUT").ok()?.trim().parse() {
+        Ok(0) | Err(..) => None,
+        Ok(secs) => Some(secs),
+    }
+}
+
+fn command_script(cmd: &str) -> String {
+    command_script_with_hint(cmd, None)
+}
+
+// The hint tells how to fix the failure (e.g. running `cargo fmt`). It is shown after the failure is
+// reported since the output of the command may not be obvious to newcomers
+fn command_script_with_hint(cmd: &str, hint: Option<&str>) -> String {
+    command_script_with_retries(cmd, hint, 0)
+}
+
+// The command is run again up to `retries` times while it fails. Each retry is announced with the
+// attempt number so that flaky failures are visible in the output
+fn command_script_with_retries(cmd: &str, hint: Option<&str>, retries: u32) -> String {
+    // Failed command is still reported in quiet mode
+    let echo = if var_os("CARGO_HUSKY_QUIET").is_some() {
+        String::new()
+    } else {
+        format!("\necho {}", shell_quote(&format!("+{}", cmd)))
+    };
+    let run = if timeout_secs().is_some() {
+        // Run the command via shell since it may not be a simple command (e.g. `FOO=1 cmd`, `a && b`)
+        format!(
+            "$cargo_husky_timeout {} -c {} \"$0\" \"$@\"",
+            shell_path(),
+            shell_quote(cmd),
+        )
+    } else {
+        cmd.to_string()
+    };
+    let run = if retries == 0 {
+        format!("\n{}\ncargo_husky_status=$?", run)
+    } else {
+        format!(
+            "\ncargo_husky_attempt=1\nwhile :; do\n    {}\n    cargo_husky_status=$?\n    if [ \"$cargo_husky_status\" -eq 0 ] || [ \"$cargo_husky_attempt\" -gt {} ]; then\n        break\n    fi\n    cargo_husky_attempt=$((cargo_husky_attempt + 1))\n    printf \"cargo-husky: '%s' failed with exit code %s, retrying (attempt %s of {})\\n\" {} \"$cargo_husky_status\" \"$cargo_husky_attempt\" >&2\ndone",
+            run,
+            retries,
+            retries + 1,
+            shell_quote(cmd),
+        )
+    };
+    let then = if cfg!(feature = "no-fail-fast") {
+        "continuing"
+    } else {
+        "aborting"
+    };
+    // With timings feature, wall-clock duration of each command is shown after it runs. `date +%s`
+    // is portable though its resolution is seconds
+    let start = if cfg!(feature = "timings") || cfg!(feature = "json-report") {
+        "\ncargo_husky_start=\"$(date +%s)\""
+    } else {
+        ""
+    };
+    let mut timing = String::new();
+    if cfg!(feature = "timings") {
+        timing.push_str(&format!(
+            "\nprintf 'cargo-husky: %s: %ss\\n' {} \"$(($(date +%s) - cargo_husky_start))\"",
+            shell_quote(cmd),
+        ));
+    }
+    if cfg!(feature = "json-report") {
+        timing.push_str(&report_record_script(cmd));
+    }
+    // Check the exit status explicitly instead of `set -e` to report which command failed. The
+    // command is put in its own line as-is
+    format!(
+        "{}{}{}{}\nif [ \"$cargo_husky_status\" -ne 0 ]; then\n    printf \"cargo-husky: '%s' failed with exit code %s, {}\\n\" {} \"$cargo_husky_status\" >&2{}\n    {}\nfi",
+        echo,
+        start,
+        run,
+        timing,
+        then,
+        shell_quote(cmd),
+        hint.map(|h| format!("\n    echo {} >&2", shell_quote(&format!("cargo-husky: {}", h))))
+            .unwrap_or_default(),
+        on_failure(),
+    )
+}
+
+// With json-report feature, a JSON object per command is appended to the file at $CARGO_HUSKY_REPORT
+// (JSON Lines). The variable is read when the hook runs. Only the exit status and the times are
+// filled by shell so the format string does not contain anything from the command
+fn report_record_script(cmd: &str) -> String {
+    format!(
+        "\nif [ -n \"${{CARGO_HUSKY_REPORT:-}}\" ]; then\n    printf '{{\"hook\":\"%s\",\"command\":%s,\"status\":%s,\"start\":%s,\"duration\":%s}}\\n' \"$cargo_husky_hook\" {} \"$cargo_husky_status\" \"$cargo_husky_start\" \"$(($(date +%s) - cargo_husky_start))\" >> \"$CARGO_HUSKY_REPORT\"\nfi",
+        shell_quote(&json_string(cmd)),
+    )
+}
+
+fn report_prelude(hook: &str) -> String {
+    if cfg!(feature = "json-report") {
+        format!("\ncargo_husky_hook={}\n", shell_quote(hook))
+    } else {
+        String::new()
+    }
+}
+
+// With no-fail-fast feature, failures are counted and following commands are still run
+fn on_failure() -> &'static str {
+    if cfg!(feature = "no-fail-fast") {
+        "cargo_husky_failures=$((${cargo_husky_failures:-0} + 1))"
+    } else {
+        "exit \"$cargo_husky_status\""
+    }
+}
+
+// Hooks such as commit-msg receive arguments from Git (e.g. path to the commit message file).
+// The command configured by the environment variable is run with all of them.
+fn forward_args_script(hook: &'static str, var: &'static str) -> Result<String> {
+    let cmd = match env::var(var) {
+        Ok(ref cmd) if !cmd.trim().is_empty() => cmd.trim().to_string(),
+        _ => return Err(Error::HookCommandNotSet(hook, var)),
+    };
+    Ok(command_script(&format!("{} \"$@\"", cmd)))
+}
+
+// `assigned` is keys of commands assigned to the hook in configuration. When it is not given, all
+// enabled commands are run
+fn feature_script(hook: &'static str, assigned: Option<&[String]>) -> Result<String> {
+    match hook {
+        "commit-msg" => return forward_args_script(hook, "CARGO_HUSKY_COMMIT_MSG_COMMAND"),
+        "prepare-commit-msg" => {
+            return forward_args_script(hook, "CARGO_HUSKY_PREPARE_COMMIT_MSG_COMMAND")
+        }
+        _ => {}
+    }
+    let cmds = cargo_commands();
+    let selected: Vec<&CargoCommand> = match assigned {
+        Some(keys) => keys
+            .iter()
+            .filter_map(|key| cmds.iter().find(|c| c.key == key))
+            .collect(),
+        None => sort_by_order(cmds.iter().filter(|c| c.enabled).collect(), &cmds),
+    };
+    Ok(commands_script(selected.iter().map(|c| c.script(hook))))
+}
+
+// Commands listed in $CARGO_HUSKY_COMMAND_ORDER separated by commas (e.g. "test,fmt") are run first in
+// the order. Other commands follow them in the default order
+fn sort_by_order<'a>(
+    mut selected: Vec<&'a CargoCommand>,
+    cmds: &[CargoCommand],
+) -> Vec<&'a CargoCommand> {
+    let order = match env::var("CARGO_HUSKY_COMMAND_ORDER") {
+        Ok(v) => v,
+        Err(..) => return selected,
+    };
+    let mut keys = vec![];
+    for key in order.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+        if cmds.iter().all(|c| c.key != key) {
+            let known = cmds.iter().map(|c| c.key).collect::<Vec<_>>().join(", ");
+            eprintln!(
+                "Warning: Unknown command {:?} in '$CARGO_HUSKY_COMMAND_ORDER' is ignored. Available commands are: {}",
+                key, known,
+            );
+            continue;
+        }
+        keys.push(key);
+    }
+    selected.sort_by_key(|c| keys.iter().position(|k| *k == c.key).unwrap_or(keys.len()));
+    selected
+}
+
+// Join scripts of commands. With parallel feature, commands are run as background jobs and their
+// outputs are shown in order after all of them finished. Each job reports its own failure
+fn commands_script<I: Iterator<Item = String>>(scripts: I) -> String {
+    let scripts = scripts.collect::<Vec<_>>();
+    if !cfg!(feature = "parallel") || scripts.len() < 2 {
+        return scripts.concat();
+    }
+
+    // Without fail-fast, a job does not exit on failure. Its exit status tells the failure instead
+    let job_status = if cfg!(feature = "no-fail-fast") {
+        "\n[ \"${cargo_husky_failures:-0}\" -eq 0 ]"
+    } else {
+        ""
+    };
+    let mut s = "\ncargo_husky_tmp=\"$(mktemp -d)\"\n".to_string();
+    for (i, script) in scripts.iter().enumerate() {
+        s.push_str(&format!(
+            "({}{}\n) >\"$cargo_husky_tmp/{}\" 2>&1 &\ncargo_husky_pid_{}=$!\n",
+            script, job_status, i, i,
+        ));
+    }
+    s.push_str("cargo_husky_failed=\n");
+    for i in 0..scripts.len() {
+        s.push_str(&format!(
+            "wait \"$cargo_husky_pid_{}\" || cargo_husky_failed=1\n",
+            i
+        ));
+    }
+    for i in 0..scripts.len() {
+        s.push_str(&format!("cat \"$cargo_husky_tmp/{}\"\n", i));
+    }
+    s.push_str(
+        "rm -rf \"$cargo_husky_tmp\"\nif [ -n \"$cargo_husky_failed\" ]; then\n    exit 1\nfi",
+    );
+    s
+}
+
+fn shell_path() -> String {
+    let default = "/bin/sh".to_string();
+    match env::var("CARGO_HUSKY_SHELL") {
+        Ok(ref sh) if sh.is_empty() => default,
+        Ok(ref sh) if sh.starts_with('/') && !sh.contains('\n') => sh.to_string(),
+        Ok(sh) => {
+            eprintln!(
+                "Warning: '$CARGO_HUSKY_SHELL' must be an absolute path but got {:?}. Using {} instead",
+                sh, default,
+            );
+            default
+        }
+        Err(..) => default,
+    }
+}
+
+// `timeout` command may not be installed (e.g. macOS without coreutils)
+fn timeout_script() -> String {
+    match timeout_secs() {
+        Some(secs) => format!(
+            r#"
+if command -v timeout >/dev/null 2>&1; then
+    cargo_husky_timeout='timeout {}'
+else
+    cargo_husky_timeout=
+fi
+"#,
+            secs
+        ),
+        None => {
+            if let Ok(v) = env::var("CARGO_HUSKY_TIMEOUT") {
+                eprintln!(
+                    "Warning: '$CARGO_HUSKY_TIMEOUT' must be a positive integer but got {:?}. Timeout is not set",
+                    v,
+                );
+            }
+            String::new()
+        }
+    }
+}
+
+// Hook can be skipped at runtime by $CARGO_HUSKY_SKIP=1 or true (case-insensitive) like husky's
+// HUSKY=0
+const SKIP_GUARD: &str = r#"
+case "${CARGO_HUSKY_SKIP:-}" in
+    1|[Tt][Rr][Uu][Ee])
+        echo 'cargo-husky: Skipped since $CARGO_HUSKY_SKIP is set'
+        exit 0
+        ;;
+esac
+"#;
+
+// pre-push hook receives the remote name and URL as arguments and the pushed refs from stdin.
+// Capture them so that commands can refer to them. Note that stdin is consumed here
+const PREPUSH_PRELUDE: &str = r#"
+cargo_husky_remote="${1:-}"
+cargo_husky_remote_url="${2:-}"
+cargo_husky_refs="$(cat)"
+export cargo_husky_remote cargo_husky_remote_url cargo_husky_refs
+"#;
+
+// post-merge hook receives 1 as its argument on squash merge. Squash merge only updates the index
+// so changes are taken from it. Otherwise they are taken from ORIG_HEAD..HEAD. When ORIG_HEAD is not
+// available, commands are run anyway
+const DEPS_CHANGED_GUARD: &str = r#"
+if [ "${1:-0}" = 1 ]; then
+    cargo_husky_changed="$(git diff --cached --name-only -- ':(glob)**/Cargo.toml' ':(glob)**/Cargo.lock')"
+else
+    cargo_husky_changed="$(git diff --name-only ORIG_HEAD HEAD -- ':(glob)**/Cargo.toml' ':(glob)**/Cargo.lock' 2>/dev/null || echo unknown)"
+fi
+if [ -z "$cargo_husky_changed" ]; then
+    echo 'cargo-husky: Skipped since neither Cargo.toml nor Cargo.lock was changed by the merge'
+    exit 0
+fi
+"#;
+
+// Git puts rebase-merge or rebase-apply directory in the git directory while rebase is in progress.
+// The git directory is resolved at runtime since each worktree has its own one. It expands to the
+// start of `if` statement whose body is run while rebase is in progress
+macro_rules! if_rebase_in_progress {
+    () => {
+        r#"cargo_husky_gitdir="$(git rev-parse --git-dir 2>/dev/null)"
+if [ -n "$cargo_husky_gitdir" ] && { [ -d "$cargo_husky_gitdir/rebase-merge" ] || [ -d "$cargo_husky_gitdir/rebase-apply" ]; }; then"#
+    };
+}
+
+const REBASE_GUARD: &str = concat!(
+    "\n",
+    if_rebase_in_progress!(),
+    r#"
+    echo 'cargo-husky: Skipped since rebase is in progress'
+    exit 0
+fi
+"#
+);
+
+// With require-clean-tree feature, pre-push hook fails when the working tree has uncommitted changes
+// since what is pushed would differ from what was tested. Untracked files are ignored when
+// $CARGO_HUSKY_ALLOW_UNTRACKED is set. The check is skipped while rebase is in progress (e.g. `git
+// push` in `git rebase --exec`) since the working tree is expected to be in the middle of changes
+fn require_clean_tree_check(hook: &str) -> String {
+    if !cfg!(feature = "require-clean-tree") || hook != "pre-push" {
+        return String::new();
+    }
+    let untracked = if var_os("CARGO_HUSKY_ALLOW_UNTRACKED").is_some() {
+        " --untracked-files=no"
+    } else {
+        ""
+    };
+    format!(
+        r#"
+{}
+    echo 'cargo-husky: Skipped checking working tree is clean since rebase is in progress'
+elif cargo_husky_dirty="$(git status --porcelain{})" && [ -n "$cargo_husky_dirty" ]; then
+    printf '%s\n' "$cargo_husky_dirty" >&2
+    echo 'cargo-husky: Working tree has uncommitted changes. Commit or stash them before pushing' >&2
+    cargo_husky_status=1
+    {}
+fi
+"#,
+        if_rebase_in_progress!(),
+        untracked,
+        on_failure(),
+    )
+}
+
+// Guard put before commands of the hook to skip them
+fn hook_guard(hook: &str) -> &'static str {
+    match hook {
+        "post-merge" if cfg!(feature = "deps-changed-only") => DEPS_CHANGED_GUARD,
+        "pre-commit" if cfg!(feature = "skip-during-rebase") => REBASE_GUARD,
+        _ => "",
+    }
+}
+
+// With clippy-fix feature, pre-commit hook applies fixes by clippy and stages them before running
+// commands. Only staged files are staged again so that unrelated files are not committed. It is
+// skipped when tracked Rust files have unstaged changes since they would be mixed with the fixes.
+// `--allow-dirty` is necessary because cargo regards untracked files as dirty. `git add` in hooks
+// does not trigger hooks again
+fn clippy_fix_stage(hook: &str) -> String {
+    if !cfg!(feature = "clippy-fix") || hook != "pre-commit" {
+        return String::new();
+    }
+    let cmd = if cfg!(feature = "run-for-all") {
+        "cargo clippy --fix --allow-dirty --allow-staged --all"
+    } else {
+        "cargo clippy --fix --allow-dirty --allow-staged"
+    };
+    let cmd = with_extra_args(
+        "clippy",
+        &with_offline("clippy", &with_toolchain("clippy", cmd)),
+    );
+    fix_and_stage_script(&with_cargo(&cmd))
+}
+
+// With fmt-fix feature, pre-commit hook formats the crate by `cargo fmt` and stages the formatted
+// files in the same way as clippy-fix. It is run after clippy-fix since fixes by clippy may not be
+// formatted
+fn fmt_fix_stage(hook: &str) -> String {
+    if !cfg!(feature = "fmt-fix") || hook != "pre-commit" {
+        return String::new();
+    }
+    let cmd = if cfg!(feature = "run-for-all") {
+        "cargo fmt --all"
+    } else {
+        "cargo fmt"
+    };
+    fix_and_stage_script(&with_cargo(&with_extra_args(
+        "fmt",
+        &with_toolchain("fmt", cmd),
+    )))
+}
+
+// Run the command modifying Rust files and stage the files which were already staged. Nothing is
+// staged when the command changed nothing. Failure of `git add` is reported as a failure of the hook
+// since the commit would not include the fixes
+fn fix_and_stage_script(cmd: &str) -> String {
+    let staged = "git diff --cached --name-only --diff-filter=ACM";
+    format!(
+        r#"
+if [ -z "$({staged} -- '*.rs')" ]; then
+    echo {no_staged}
+elif ! git diff --quiet -- '*.rs'; then
+    echo {unstaged}
+else{run}
+    if ! git diff --quiet -- '*.rs'; then
+        if ! {staged} -z -- '*.rs' | xargs -0 git add --; then
+            echo 'cargo-husky: Failed to stage the fixed files' >&2
+            cargo_husky_status=1
+            {on_failure}
+        fi
+        if ! git diff --quiet -- '*.rs'; then
+            echo {not_staged}
+            git diff --name-only -- '*.rs'
+        fi
+    fi
+fi
+"#,
+        staged = staged,
+        no_staged = shell_quote(&format!("No Rust file is staged. Skipped `{}`", cmd)),
+        unstaged = shell_quote(&format!(
+            "cargo-husky: Skipped `{}` since Rust files have unstaged changes",
+            cmd,
+        )),
+        run = command_script(cmd).replace('\n', "\n    "),
+        on_failure = on_failure(),
+        not_staged = shell_quote(&format!(
+            "cargo-husky: `{}` also changed files which are not staged. They are left unstaged:",
+            cmd,
+        )),
+    )
+}
+
+// With forbid-patterns feature, pre-commit hook fails when staged Rust files contain debugging
+// leftovers. Patterns are fixed strings given by $CARGO_HUSKY_FORBIDDEN_PATTERNS separated by commas.
+// Contents in the index are searched since they are what will be committed
+fn forbid_patterns_check(hook: &str) -> String {
+    if !cfg!(feature = "forbid-patterns") || hook != "pre-commit" {
+        return String::new();
+    }
+    let patterns = forbidden_patterns();
+    let staged = "git diff --cached --name-only --diff-filter=ACM";
+    format!(
+        r#"
+if [ -n "$({staged} -- '*.rs')" ]; then
+    cargo_husky_matches="$({staged} -z -- '*.rs' | xargs -0 git grep --cached -n -F {args} --)"
+    if [ -n "$cargo_husky_matches" ]; then
+        printf '%s\n' "$cargo_husky_matches" >&2
+        echo {msg} >&2
+        cargo_husky_status=1
+        {on_failure}
+    fi
+fi
+"#,
+        staged = staged,
+        args = patterns
+            .iter()
+            .map(|p| format!("-e {}", shell_quote(p)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        msg = shell_quote(&format!(
+            "cargo-husky: Forbidden patterns were found in staged files: {}",
+            patterns.join(", "),
+        )),
+        on_failure = on_failure(),
+    )
+}
+
+fn forbidden_patterns() -> Vec<String> {
+    let default = vec!["dbg!".to_string(), "todo!".to_string()];
+    let value = match env::var("CARGO_HUSKY_FORBIDDEN_PATTERNS") {
+        Ok(value) => value,
+        Err(..) => return default,
+    };
+    let patterns = value
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if patterns.is_empty() || value.contains(['\n', '\r']) {
+        eprintln!(
+            "Warning: '$CARGO_HUSKY_FORBIDDEN_PATTERNS' must be non-empty patterns separated by commas in a single line but got {:?}. `dbg!,todo!` is used instead",
+            value,
+        );
+        return default;
+    }
+    patterns
+}
+
+// With forbid-large-files feature, pre-commit hook fails when staged files are larger than
+// $CARGO_HUSKY_MAX_FILE_SIZE. Sizes of blobs in the index are checked since working tree may differ.
+// Renames are split into additions so that moved files are also checked. Deleted files and
+// submodules (mode 160000) are skipped
+fn forbid_large_files_check(hook: &str) -> String {
+    if !cfg!(feature = "forbid-large-files") || hook != "pre-commit" {
+        return String::new();
+    }
+    let max = max_file_size();
+    format!(
+        r#"
+cargo_husky_large_files="$(git diff --cached --raw --no-abbrev --no-renames --diff-filter=ACM | awk '$2 != "160000" {{ print $4, substr($0, index($0, "\t") + 1) }}' | git cat-file --batch-check='%(objectsize) %(rest)' | awk -v max={max} '$1 > max {{ size = $1; sub(/^[0-9]+ /, ""); printf "%s (%s bytes)\n", $0, size }}')"
+if [ -n "$cargo_husky_large_files" ]; then
+    printf '%s\n' "$cargo_husky_large_files" >&2
+    echo {msg} >&2
+    cargo_husky_status=1
+    {on_failure}
+fi
+"#,
+        max = max,
+        msg = shell_quote(&format!(
+            "cargo-husky: Staged files larger than {} bytes were found",
+            max,
+        )),
+        on_failure = on_failure(),
+    )
+}
+
+const DEFAULT_MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+fn max_file_size() -> u64 {
+    let value = match env::var("CARGO_HUSKY_MAX_FILE_SIZE") {
+        Ok(value) => value,
+        Err(..) => return DEFAULT_MAX_FILE_SIZE,
+    };
+    parse_file_size(&value).unwrap_or_else(|| {
+        eprintln!(
+            "Warning: '$CARGO_HUSKY_MAX_FILE_SIZE' must be a size in bytes optionally followed by K, M or G but got {:?}. 5M is used instead",
+            value,
+        );
+        DEFAULT_MAX_FILE_SIZE
+    })
+}
+
+// Size such as "1048576", "512K", "5M" or "1G". Units are powers of 1024
+fn parse_file_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().last()? {
+        (i, 'K') | (i, 'k') => (&s[..i], 1024),
+        (i, 'M') | (i, 'm') => (&s[..i], 1024 * 1024),
+        (i, 'G') | (i, 'g') => (&s[..i], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    if num.is_empty() || !num.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    num.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+// With require-locked feature, pre-push hook fails when Cargo.lock is out of date. `cargo tree` is
+// used since it does not access the network. Other errors (e.g. packages not fetched yet) are only
+// reported so that they don't block pushing
+fn require_locked_check(hook: &str) -> String {
+    if !cfg!(feature = "require-locked") || hook != "pre-push" {
+        return String::new();
+    }
+    format!(
+        r#"
+if ! cargo_husky_output="$({} 2>&1 >/dev/null)"; then
+    case "$cargo_husky_output" in
+        *"--locked was passed"*)
+            echo 'cargo-husky: Cargo.lock is out of date. Run `cargo update` and commit Cargo.lock' >&2
+            cargo_husky_status=1
+            {}
+            ;;
+        *)
+            printf '%s\n' "$cargo_husky_output" >&2
+            echo 'cargo-husky: Could not check Cargo.lock is up to date. Skipped' >&2
+            ;;
+    esac
+fi
+"#,
+        with_cargo("cargo tree --locked"),
+        on_failure(),
+    )
+}
+
+// $CARGO_HUSKY_PRE_COMMAND is run before all commands (e.g. starting a database container) and
+// $CARGO_HUSKY_POST_COMMAND is run on exiting the hook even if some command failed. The trap is set
+// before the pre-command so that partial setup is also torn down
+fn pre_post_script() -> String {
+    let command = |var| match env::var(var) {
+        Ok(cmd) if !cmd.trim().is_empty() => Some(cmd.trim().to_string()),
+        _ => None,
+    };
+    let mut script = String::new();
+    if let Some(post) = command("CARGO_HUSKY_POST_COMMAND") {
+        script.push_str(&format!("\ntrap {} EXIT", shell_quote(&post)));
+    }
+    if let Some(pre) = command("CARGO_HUSKY_PRE_COMMAND") {
+        script.push_str(&command_script(&pre));
+    }
+    script
+}
+
+fn hook_prelude(hook: &str) -> &'static str {
+    match hook {
+        "pre-push" => PREPUSH_PRELUDE,
+        _ => "",
+    }
+}
+
+// With load-dotenv feature, variables in .env at the root of repository are exported before running
+// commands. The file is parsed instead of sourced so that malformed lines are skipped rather than
+// breaking the hook. Variables already set in the environment are not overwritten
+const DOTENV_SCRIPT: &str = r#"
+cargo_husky_dotenv="$(git rev-parse --show-toplevel 2>/dev/null || pwd)/.env"
+if [ -f "$cargo_husky_dotenv" ]; then
+    cargo_husky_cr="$(printf '\r')"
+    while IFS= read -r cargo_husky_line || [ -n "$cargo_husky_line" ]; do
+        cargo_husky_line="${cargo_husky_line%"$cargo_husky_cr"}"
+        cargo_husky_line="${cargo_husky_line#export }"
+        cargo_husky_key="${cargo_husky_line%%=*}"
+        cargo_husky_value="${cargo_husky_line#*=}"
+        case "$cargo_husky_key" in
+            "$cargo_husky_line"|''|[0-9]*|*[!A-Za-z0-9_]*) continue ;;
+        esac
+        case "$cargo_husky_value" in
+            \"*\"|\'*\')
+                cargo_husky_value="${cargo_husky_value#?}"
+                cargo_husky_value="${cargo_husky_value%?}"
+                ;;
+        esac
+        if eval "[ -z \"\${$cargo_husky_key+x}\" ]"; then
+            export "$cargo_husky_key=$cargo_husky_value"
+        fi
+    done < "$cargo_husky_dotenv"
+fi
+"#;
+
+// With banner feature, a line is printed at the start so that users know what the hook is doing (e.g.
+// in GUI clients which show the output of hooks). It is omitted in quiet mode
+fn banner_script(hook: &str) -> String {
+    if cfg!(feature = "banner") && var_os("CARGO_HUSKY_QUIET").is_none() {
+        format!("\necho 'cargo-husky: Running {} checks...'\n", hook)
+    } else {
+        String::new()
+    }
+}
+
+// GUI Git clients are often launched without a login shell so $PATH does not include the directory
+// where cargo and rustup proxies are installed. With fix-path feature, it is appended to $PATH only
+// when it exists and is not in $PATH yet. Directories in $PATH still take precedence. It is put after
+// .env is loaded since $CARGO_HOME may be set there
+const FIX_PATH_SCRIPT: &str = r#"
+for cargo_husky_dir in "${CARGO_HOME:-${HOME:-}/.cargo}/bin" "${HOME:-}/.cargo/bin"; do
+    if [ -d "$cargo_husky_dir" ]; then
+        case ":$PATH:" in
+            *":$cargo_husky_dir:"*) ;;
+            *) PATH="$PATH:$cargo_husky_dir" ;;
+        esac
+    fi
+done
+export PATH
+"#;
+
+fn fix_path_script() -> &'static str {
+    if cfg!(feature = "fix-path") {
+        FIX_PATH_SCRIPT
+    } else {
+        ""
+    }
+}
+
+// With no-color feature, commands run by hooks don't emit ANSI escapes which some GUI clients show
+// as garbage. Exported variables only affect the hook process and its children
+fn no_color_script() -> &'static str {
+    if cfg!(feature = "no-color") {
+        "\nexport CARGO_TERM_COLOR=never NO_COLOR=1\n"
+    } else {
+        ""
+    }
+}
+
+fn dotenv_script() -> &'static str {
+    if cfg!(feature = "load-dotenv") {
+        DOTENV_SCRIPT
+    } else {
+        ""
+    }
+}
+
+// Put at the end of hook with no-fail-fast feature to fail after all commands were run
+const FAILURES_CHECK: &str = r#"
+if [ "${cargo_husky_failures:-0}" -ne 0 ]; then
+    echo "cargo-husky: $cargo_husky_failures command(s) failed" >&2
+    exit 1
+fi"#;
+
+// Paths in comments of generated shell scripts use '/' as separator even on Windows
+fn slash_path(path: &str) -> String {
+    path.replace(path::MAIN_SEPARATOR, "/")
+}
+
+// Building the crate which depends on cargo-husky generates hooks, so the comment points to its
+// manifest rather than to build.rs in the source of cargo-husky. The path is relative to the root of
+// the repository so that it does not depend on the machine
+fn generated_by_comment() -> String {
+    let manifest = match find_manifest() {
+        Ok(Some(path)) => path,
+        _ => return "# Generated by cargo-husky".to_string(),
+    };
+    let path = match repo_root() {
+        Ok(root) => manifest
+            .strip_prefix(&root)
+            .map(Path::to_path_buf)
+            .unwrap_or(manifest),
+        Err(..) => manifest,
+    };
+    format!(
+        "# Generated by building {}",
+        slash_path(&path.to_string_lossy())
+    )
+}
+
+fn write_script<W: io::Write>(w: &mut W, hook: &str, script: &str) -> Result<()> {
+    writeln!(
+        w,
+        r#"#!{}
+#
+# This hook was set by cargo-husky v{}: {}
+{}
+{}
+# Output at {}
+#
+{}{}{}{}{}{}{}{}{}"#,
+        shell_path(),
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_HOMEPAGE"),
+        marker_comment(),
+        generated_by_comment(),
+        slash_path(&env::var("OUT_DIR").unwrap_or_else(|_| "".to_string())),
+        SKIP_GUARD,
+        banner_script(hook),
+        report_prelude(hook),
+        hook_prelude(hook),
+        dotenv_script(),
+        fix_path_script(),
+        no_color_script(),
+        timeout_script(),
+        script
+    )?;
+    Ok(())
+}
+
+// Hooks directory may not be writable (e.g. read-only checkout). It should not break the build
+fn create_hook_file(path: &Path) -> Result<File> {
+    create_executable_file(path).map_err(|err| {
+        if is_not_writable(&err) {
+            Error::HookDirNotWritable(path.parent().unwrap_or(path).to_owned())
+        } else {
+            Error::Io(err)
+        }
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn create_executable_file(path: &Path) -> io::Result<File> {
+    File::create(path)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn create_executable_file(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o755)
+        .open(path)
+}
+
+#[cfg(target_os = "windows")]
+fn copy_permissions(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Permission bits of the user hook are kept as-is except for setuid, setgid and sticky bits which
+// should not be copied to hooks. 0o755 is used when they cannot be read. Hooks in .husky directory
+// may not be executable, but Git runs only executable hooks
+#[cfg(not(target_os = "windows"))]
+fn copy_permissions(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut mode = match fs::metadata(src) {
+        Ok(md) => md.permissions().mode() & 0o777,
+        Err(..) => 0o755,
+    };
+    if mode & 0o111 == 0 {
+        mode |= 0o111;
+    }
+    fs::set_permissions(dst, fs::Permissions::from_mode(mode))
+}
+
+fn local_hook_path(hook_path: &Path) -> PathBuf {
+    let mut name = hook_path.file_name().unwrap().to_owned();
+    name.push(".local");
+    hook_path.with_file_name(name)
+}
+
+fn backup_hook_path(hook_path: &Path) -> PathBuf {
+    let mut name = hook_path.file_name().unwrap().to_owned();
+    name.push(".bak");
+    hook_path.with_file_name(name)
+}
+
+// What was done for each hook. They are reported at the end of installation
+#[derive(Clone, Copy, PartialEq)]
+enum Disposition {
+    Installed,
+    Overwritten,
+    SkippedForeign,
+    SkippedSameVersion,
+    SkippedNewerVersion,
+    SkippedFrozen,
+}
+
+impl Disposition {
+    const ALL: &'static [Disposition] = &[
+        Disposition::Installed,
+        Disposition::Overwritten,
+        Disposition::SkippedForeign,
+        Disposition::SkippedSameVersion,
+        Disposition::SkippedNewerVersion,
+        Disposition::SkippedFrozen,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Disposition::Installed => "installed",
+            Disposition::Overwritten => "overwritten",
+            Disposition::SkippedForeign => "skipped-foreign",
+            Disposition::SkippedSameVersion => "skipped-same-version",
+            Disposition::SkippedNewerVersion => "skipped-newer-version",
+            Disposition::SkippedFrozen => "skipped-frozen",
+        }
+    }
+
+    fn skipped(hook: &Path) -> Disposition {
+        verbose_skipped(hook);
+        let installed = find_marker_line(hook).ok().and_then(|l| l);
+        match installed.as_deref().and_then(hook_version) {
+            Some(v)
+                if compare_versions(v, env!("CARGO_PKG_VERSION")) == Some(Ordering::Greater) =>
+            {
+                Disposition::SkippedNewerVersion
+            }
+            Some(..) => Disposition::SkippedSameVersion,
+            None => Disposition::SkippedForeign,
+        }
+    }
+
+    fn written(existed: bool) -> Disposition {
+        if existed {
+            Disposition::Overwritten
+        } else {
+            Disposition::Installed
+        }
+    }
+}
+
+// Outcome of installing each hook. Every hook is attempted even if some of them failed so that a
+// failure of one hook (e.g. permission error) does not prevent installing the others
+#[derive(Default)]
+struct Report {
+    dispositions: Vec<(String, Disposition)>,
+    failures: Vec<(String, Error)>,
+}
+
+impl Report {
+    fn add(&mut self, hook: &str, result: Result<Disposition>) {
+        match result {
+            Ok(disposition) => self.dispositions.push((hook.to_string(), disposition)),
+            Err(err) => self.failures.push((hook.to_string(), err)),
+        }
+    }
+
+    fn add_failure(&mut self, hook: &str, result: Result<()>) {
+        if let Err(err) = result {
+            self.failures.push((hook.to_string(), err));
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.dispositions.is_empty() && self.failures.is_empty()
+    }
+
+    // Report installed hooks, then fail with errors of all failed hooks together. Returns whether
+    // any hook was installed
+    fn finish(self) -> Result<bool> {
+        report_dispositions(&self.dispositions);
+        let installed = !self.dispositions.is_empty();
+        if self.failures.is_empty() {
+            Ok(installed)
+        } else {
+            Err(Error::HooksFailed(self.failures, installed))
+        }
+    }
+}
+
+// Report hooks grouped by their dispositions in a few lines. e.g.
+//   installed: pre-push, pre-commit
+//   skipped-foreign: post-merge
+fn report_dispositions(report: &[(String, Disposition)]) {
+    for &disposition in Disposition::ALL {
+        let hooks: Vec<&str> = report
+            .iter()
+            .filter(|(_, d)| *d == disposition)
+            .map(|(h, _)| h.as_str())
+            .collect();
+        if !hooks.is_empty() {
+            println!(
+                "cargo:warning={}: {}",
+                disposition.label(),
+                hooks.join(", "),
+            );
+        }
+    }
+}
+
+fn verbose_skipped(hook: &Path) {
+    if is_our_hook(hook) {
+        let line = find_marker_line(hook)
+            .ok()
+            .and_then(|l| l)
+            .unwrap_or_default();
+        verbose!(
+            "Skipped {}: already generated by cargo-husky v{}",
+            hook.display(),
+            hook_version(&line).unwrap_or("?"),
+        );
+    } else {
+        verbose!(
+            "Skipped {}: hook put by someone else exists",
+            hook.display()
+        );
+    }
+}
+
+fn orig_hook_path(hook_path: &Path) -> PathBuf {
+    let mut name = hook_path.file_name().unwrap().to_owned();
+    name.push(".orig");
+    hook_path.with_file_name(name)
+}
+
+// With force-overwrite feature, a hook put by someone else is moved to <hook>.orig so that our hook
+// is written instead. Only the latest one is kept
+fn take_over_foreign_hook(hook: &Path) -> Result<()> {
+    if !cfg!(feature = "force-overwrite") || !hook.is_file() || is_our_hook(hook) {
+        return Ok(());
+    }
+    let orig = orig_hook_path(hook);
+    fs::rename(hook, &orig)?;
+    println!(
+        "cargo:warning=Overwriting {} put by someone else since force-overwrite feature is enabled. The original was moved to {}",
+        hook.display(),
+        orig.display(),
+    );
+    Ok(())
+}
+
+// Keep the hook generated by other version of cargo-husky as <hook>.bak before overwriting it. Only
+// one backup is kept
+fn backup_hook(hook: &Path) -> Result<()> {
+    if is_our_hook(hook) {
+        let backup = backup_hook_path(hook);
+        fs::copy(hook, &backup)?;
+        verbose!("Backed up {} to {}", hook.display(), backup.display());
+    }
+    Ok(())
+}
+
+// Hooks listed in $CARGO_HUSKY_FREEZE separated by commas are never written nor removed, even when
+// cargo-husky is updated. It allows customizing a generated hook by hand while keeping others
+fn is_frozen(hook: &str) -> bool {
+    env::var("CARGO_HUSKY_FREEZE")
+        .map(|v| v.split(',').any(|h| h.trim() == hook))
+        .unwrap_or(false)
+}
+
+fn install_hook(hook: &str, script: &str) -> Result<Disposition> {
+    if is_frozen(hook) {
+        verbose!("Skipped {}: frozen by $CARGO_HUSKY_FREEZE", hook);
+        return Ok(Disposition::SkippedFrozen);
+    }
+    let hook_path = create_hooks_dir()?.join(hook);
+    let local_path = local_hook_path(&hook_path);
+
+    if cfg!(feature = "chain-existing")
+        && hook_path.is_file()
+        && !is_our_hook(&hook_path)
+        && !local_path.exists()
+    {
+        // Move the hook put by someone else aside and run it from our hook
+        fs::rename(&hook_path, &local_path)?;
+        verbose!(
+            "Moved existing hook {} to {}",
+            hook_path.display(),
+            local_path.display(),
+        );
+    }
+
+    let existed = hook_path.is_file();
+    take_over_foreign_hook(&hook_path)?;
+    if hook_already_exists(&hook_path) {
+        return Ok(Disposition::skipped(&hook_path));
+    }
+
+    // Chained hook is not skipped by the guard
+    let mut script = format!(
+        "{}{}{}{}{}{}{}{}{}",
+        hook_guard(hook),
+        pre_post_script(),
+        clippy_fix_stage(hook),
+        fmt_fix_stage(hook),
+        forbid_patterns_check(hook),
+        forbid_large_files_check(hook),
+        require_locked_check(hook),
+        require_clean_tree_check(hook),
+        script,
+    );
+    if cfg!(feature = "chain-existing") && local_path.is_file() {
+        let mut local = format!("\"$(dirname \"$0\")/{}.local\" \"$@\"", hook);
+        if hook == "pre-push" {
+            // Pass the refs consumed by the prelude to the chained hook
+            local.insert_str(0, "printf '%s\\n' \"$cargo_husky_refs\" | ");
+        }
+        script.insert_str(0, &command_script(&local));
+    }
+    if cfg!(feature = "no-fail-fast") {
+        script.push_str(FAILURES_CHECK);
+    }
+    let mut content = vec![];
+    write_script(&mut content, hook, &script)?;
+    if cfg!(feature = "posix-strict") {
+        check_posix_script(&content)?;
+    }
+    backup_hook(&hook_path)?;
+    create_hook_file(&hook_path)?.write_all(&content)?;
+    verbose!("Wrote hook {}", hook_path.display());
+    Ok(Disposition::written(existed))
+}
+
+// With posix-strict feature, generated hooks are checked as POSIX sh by `sh -n` and by shellcheck
+// when it is installed before they are written. Bash-specific constructs sneaking into snippets of
+// features then break the build instead of the hooks
+fn check_posix_script(script: &[u8]) -> Result<()> {
+    run_script_checker("sh", &["-n"], script)?;
+    run_script_checker(
+        "shellcheck",
+        &["--shell=sh", "--severity=error", "-"],
+        script,
+    )
+}
+
+// The script is given via stdin. Checkers which are not installed are skipped
+fn run_script_checker(cmd: &'static str, args: &[&str], script: &[u8]) -> Result<()> {
+    let mut child = match Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            verbose!("Skipped checking hook script by {}: not installed", cmd);
+            return Ok(());
+        }
+        Err(err) => return Err(Error::Io(err)),
+    };
+    // Checkers read the whole script before writing their output so this does not block
+    child.stdin.take().unwrap().write_all(script)?;
+    let out = child.wait_with_output()?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let mut output = String::from_utf8_lossy(&out.stdout).into_owned();
+    output.push_str(&String::from_utf8_lossy(&out.stderr));
+    Err(Error::InvalidHookScript(cmd, output))
+}
+
+fn uninstall_hook(hook: &str) -> Result<()> {
+    if is_frozen(hook) {
+        return Ok(());
+    }
+    let hook_path = resolve_hooks_dir()?.join(hook);
+    // Never remove hooks put by someone else
+    if is_our_hook(&hook_path) {
+        // Hooks generated by newer cargo-husky are not removed as well as they are not downgraded
+        if let Some(installed) = newer_hook_version(&hook_path) {
+            println!(
+                "cargo:warning={} was generated by newer cargo-husky v{}. It is not removed by v{}",
+                hook_path.display(),
+                installed,
+                env!("CARGO_PKG_VERSION"),
+            );
+            return Ok(());
+        }
+        fs::remove_file(&hook_path)?;
+        verbose!("Removed hook {}", hook_path.display());
+        // Restore the hook which was moved aside by chain-existing feature
+        let local_path = local_hook_path(&hook_path);
+        if local_path.is_file() {
+            fs::rename(&local_path, &hook_path)?;
+            verbose!(
+                "Restored hook {} from {}",
+                hook_path.display(),
+                local_path.display(),
+            );
+        }
+    } else if hook_path.exists() {
+        verbose!(
+            "Left {} untouched: hook put by someone else",
+            hook_path.display(),
+        );
+    }
+    Ok(())
+}
+
+fn install_user_hook(src: &Path, dst: &Path, name: &ffi::OsStr) -> Result<Disposition> {
+    let dst_file_path = dst.join(name);
+    let existed = dst_file_path.is_file();
+    take_over_foreign_hook(&dst_file_path)?;
+    if hook_already_exists(&dst_file_path) {
+        return Ok(Disposition::skipped(&dst_file_path));
+    }
+
+    // Copy the content byte-for-byte. Only the header lines are inserted with the same line ending as
+    // the first line so that the script is not mangled (e.g. CRLF-sensitive here-document)
+    let mut content = vec![];
+    File::open(src)?.read_to_end(&mut content)?;
+
+    if content.is_empty() {
+        return Err(Error::EmptyUserHook(src.to_owned()));
+    }
+
+    let first_line_len = content
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|idx| idx + 1)
+        .unwrap_or(content.len());
+    let newline = if content[..first_line_len].ends_with(b"\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+
+    // Insert cargo-husky package version information as comment at the third line
+    let header = format!(
+        "#{}# This hook was set by cargo-husky v{}: {}{}{}{}",
+        newline,
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_HOMEPAGE"),
+        newline,
+        marker_comment(),
+        newline,
+    );
+
+    backup_hook(&dst_file_path)?;
+    // The hook may be read-only since permissions are copied from the user hook
+    if dst_file_path.is_file() {
+        fs::remove_file(&dst_file_path).map_err(|err| match err.kind() {
+            io::ErrorKind::PermissionDenied => Error::HookDirNotWritable(dst.to_owned()),
+            _ => Error::Io(err),
+        })?;
+    }
+    let mut f = io::BufWriter::new(create_hook_file(&dst_file_path)?);
+    if content.starts_with(b"#!") {
+        f.write_all(&content[..first_line_len])?;
+        if !content[..first_line_len].ends_with(b"\n") {
+            f.write_all(newline.as_bytes())?;
+        }
+        f.write_all(header.as_bytes())?;
+        f.write_all(&content[first_line_len..])?;
+    } else {
+        f.write_all(b"#")?;
+        f.write_all(newline.as_bytes())?;
+        f.write_all(header.as_bytes())?;
+        f.write_all(&content)?;
+    }
+    f.flush()?;
+    copy_permissions(src, &dst_file_path)?;
+    verbose!(
+        "Wrote user hook {} from {}",
+        dst_file_path.display(),
+        src.display(),
+    );
+
+    Ok(Disposition::written(existed))
+}
+
+// Windows has no executable bit. A file without extension (e.g. pre-commit) or a script starting with
+// shebang is regarded as a hook so that other files such as README.md are not installed
+#[cfg(target_os = "windows")]
+fn is_executable_file(entry: &fs::DirEntry) -> bool {
+    match entry.file_type() {
+        Ok(ft) if ft.is_file() => {}
+        _ => return false,
+    }
+    let name = entry.file_name();
+    let name = name.to_string_lossy();
+    if name.starts_with('.') || name.ends_with('~') {
+        return false; // Hidden files and editor backup files
+    }
+    let path = entry.path();
+    if path.extension().is_none() {
+        return true;
+    }
+    let mut head = [0; 2];
+    match File::open(&path).and_then(|mut f| f.read_exact(&mut head)) {
+        Ok(()) => &head == b"#!",
+        Err(..) => false,
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_executable_file(entry: &fs::DirEntry) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let ft = match entry.file_type() {
+        Ok(ft) => ft,
+        Err(..) => return false,
+    };
+    if !ft.is_file() {
+        return false;
+    }
+    let md = match entry.metadata() {
+        Ok(md) => md,
+        Err(..) => return false,
+    };
+    let mode = md.permissions().mode();
+    mode & 0o555 == 0o555 // Check file is read and executable mode
+}
+
+// Root directory of the repository where .git directory is put
+fn repo_root() -> Result<PathBuf> {
+    let mut dir = find_gitdir()?;
+    dir.pop();
+    Ok(dir)
+}
+
+fn cargo_husky_dir() -> Result<PathBuf> {
+    Ok(repo_root()?.join(".cargo-husky"))
+}
+
+// Files in subdirectories of user hooks directory are helpers referenced by hooks. They are copied
+// as-is with their permissions, preserving the directory structure
+fn copy_user_hook_helpers(src: &Path, dst: &Path) -> Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dst = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_user_hook_helpers(&entry.path(), &dst)?;
+        } else {
+            fs::copy(entry.path(), &dst)?;
+            verbose!("Copied helper {}", dst.display());
+        }
+    }
+    Ok(())
+}
+
+// Hook names run by Git. Hook scripts in .husky directory are found by their names since husky v9
+// does not require them to be executable
+const GIT_HOOK_NAMES: &[&str] = &[
+    "applypatch-msg",
+    "pre-applypatch",
+    "post-applypatch",
+    "pre-commit",
+    "pre-merge-commit",
+    "prepare-commit-msg",
+    "commit-msg",
+    "post-commit",
+    "pre-rebase",
+    "post-checkout",
+    "post-merge",
+    "pre-push",
+    "pre-auto-gc",
+    "post-rewrite",
+];
+
+// Directories to find user hooks and whether it is .husky directory used by husky v5+. When the same
+// hook is found in multiple directories, the one in the earlier directory is used
+fn user_hooks_dirs() -> Result<Vec<(PathBuf, bool)>> {
+    // $CARGO_HUSKY_HOOKS_DIR overrides the directories relative to the root of repository. Multiple
+    // directories are separated by ':' (';' on Windows) like $PATH
+    if let Some(dirs) = var_os("CARGO_HUSKY_HOOKS_DIR") {
+        let root = repo_root()?;
+        let dirs = env::split_paths(&dirs)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| (root.join(dir), false))
+            .collect::<Vec<_>>();
+        if !dirs.is_empty() {
+            return Ok(dirs);
+        }
+    }
+    let mut dirs = vec![(cargo_husky_dir()?.join("hooks"), false)];
+    // .husky may be left by husky of JavaScript, so it is used only when the feature is enabled
+    if cfg!(feature = "husky-hooks") {
+        dirs.push((repo_root()?.join(".husky"), true));
+    }
+    Ok(dirs)
+}
+
+// A comment directive such as `# cargo-husky: hook=pre-push` at the top of a user hook tells which Git
+// hook the file is installed as so that the file can be named descriptively. `None` means that the
+// file name is used
+fn hook_directive(path: &Path) -> Result<Option<String>> {
+    let reader = io::BufReader::new(File::open(path)?);
+    for line in reader.split(b'\n').take(10) {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        let directive = match line
+            .trim()
+            .strip_prefix('#')
+            .and_then(|l| l.trim_start().strip_prefix("cargo-husky:"))
+        {
+            Some(directive) => directive.trim(),
+            None => continue,
+        };
+        return match directive.strip_prefix("hook=") {
+            Some(hook) if GIT_HOOK_NAMES.contains(&hook) => Ok(Some(hook.to_string())),
+            _ => Err(Error::InvalidHookDirective(
+                path.to_owned(),
+                directive.to_string(),
+            )),
+        };
+    }
+    Ok(None)
+}
+
+fn install_user_hooks() -> Result<Report> {
+    let mut dirs = user_hooks_dirs()?;
+    let default_dir = dirs[0].0.clone();
+    dirs.retain(|(dir, _)| dir.is_dir());
+    let user_hooks_dir = match dirs.first() {
+        Some((dir, _)) => dir.clone(),
+        None => return Err(Error::InvalidUserHooksDir(default_dir)),
+    };
+
+    // Pairs of the hook name and the path of the user hook
+    let mut hook_paths: Vec<(ffi::OsString, PathBuf)> = vec![];
+    let mut helper_dirs: Vec<PathBuf> = vec![];
+    for (dir, is_husky) in dirs {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                let name = entry.file_name();
+                if helper_dirs.iter().all(|d| d.file_name() != Some(&name)) {
+                    helper_dirs.push(entry.path());
+                }
+                continue;
+            }
+            if !is_husky && !is_executable_file(&entry) {
+                continue;
+            }
+            let name = match hook_directive(&entry.path())? {
+                Some(hook) => ffi::OsString::from(hook),
+                None => entry.file_name(),
+            };
+            if is_husky && GIT_HOOK_NAMES.iter().all(|h| ffi::OsStr::new(h) != name) {
+                continue;
+            }
+            if hook_paths.iter().any(|(n, _)| *n == name) {
+                verbose!(
+                    "Ignored {} since the same hook is found",
+                    entry.path().display()
+                );
+                continue;
+            }
+            hook_paths.push((name, entry.path()));
+        }
+    }
+
+    if hook_paths.is_empty() {
+        return Err(Error::InvalidUserHooksDir(user_hooks_dir));
+    }
+
+    let hooks_dir = create_hooks_dir()?;
+    let mut report = Report::default();
+    for (name, path) in hook_paths {
+        report.add(
+            &name.to_string_lossy(),
+            install_user_hook(&path, &hooks_dir, &name),
+        );
+    }
+    for dir in helper_dirs {
+        let name = dir.file_name().unwrap();
+        report.add_failure(
+            &format!("{}/", name.to_string_lossy()),
+            copy_user_hook_helpers(&dir, &hooks_dir.join(name)),
+        );
+    }
+
+    Ok(report)
+}
+
+// Target directory is put in the root of workspace by default. When OUT_DIR is outside the
+// repository (e.g. $CARGO_TARGET_DIR), the manifest at the root of the repository is used as the git
+// directory is found from the fallback
+fn find_manifest() -> Result<Option<PathBuf>> {
+    let root = repo_root()?;
+    let mut dir = out_dir()?;
+    if !dir.starts_with(&root) {
+        dir = root.clone();
+    }
+    loop {
+        let manifest = dir.join("Cargo.toml");
+        if manifest.is_file() {
+            return Ok(Some(manifest));
+        }
+        if dir == root || !dir.pop() {
+            return Ok(None);
+        }
+    }
+}
+
+// Configuration in .cargo-husky/config.toml, or in [package.metadata.cargo-husky] or
+// [workspace.metadata.cargo-husky] section of Cargo.toml
+struct Config {
+    path: PathBuf,
+    table: toml::Table,
+}
+
+// Whether the key is in [package.metadata.cargo-husky] or [workspace.metadata.cargo-husky]
+fn is_metadata_key(key: &[String]) -> bool {
+    key.len() >= 3
+        && (key[0] == "package" || key[0] == "workspace")
+        && key[1] == "metadata"
+        && key[2] == "cargo-husky"
+}
+
+impl Config {
+    fn parse(path: &Path) -> Result<toml::Table> {
+        let mut src = String::new();
+        File::open(path)?.read_to_string(&mut src)?;
+        toml::parse(&src).map_err(|msg| Error::InvalidConfig(path.to_owned(), msg))
+    }
+
+    // Cargo.toml is validated by cargo. Errors outside cargo-husky sections are caused by syntax this
+    // reader does not support, so they are ignored not to break builds
+    fn parse_manifest(path: &Path) -> Result<toml::Table> {
+        let mut src = String::new();
+        File::open(path)?.read_to_string(&mut src)?;
+        let (table, errors) = toml::parse_lenient(&src);
+        for (key, msg) in errors {
+            if is_metadata_key(&key) {
+                return Err(Error::InvalidConfig(path.to_owned(), msg));
+            }
+            verbose!("Ignored error in {}: {}", path.display(), msg);
+        }
+        Ok(table)
+    }
+
+    // Configuration file takes precedence over metadata in Cargo.toml
+    fn read() -> Result<Option<Config>> {
+        let path = cargo_husky_dir()?.join("config.toml");
+        if !path.is_file() {
+            return Config::read_metadata();
+        }
+        let table = Config::parse(&path)?;
+        Ok(Some(Config { path, table }))
+    }
+
+    fn read_metadata() -> Result<Option<Config>> {
+        let path = match find_manifest()? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let table = Config::parse_manifest(&path)?
+            .into_iter()
+            .filter_map(|(key, value)| {
+                if key.len() > 3 && is_metadata_key(&key) {
+                    Some((key[3..].to_vec(), value))
+                } else {
+                    None
+                }
+            })
+            .collect::<toml::Table>();
+
+        if table.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Config { path, table }))
+    }
+
+    fn invalid(&self, msg: String) -> Error {
+        Error::InvalidConfig(self.path.clone(), msg)
+    }
+
+    fn strings(&self, key: &str, value: &toml::Value) -> Result<Vec<String>> {
+        let elems = match value {
+            toml::Value::Array(elems) => elems,
+            _ => return Err(self.invalid(format!("'{}' must be an array of strings", key))),
+        };
+        elems
+            .iter()
+            .map(|elem| match elem {
+                toml::Value::String(s) => Ok(s.clone()),
+                _ => Err(self.invalid(format!("'{}' must be an array of strings", key))),
+            })
+            .collect()
+    }
+
+    // Command is a string or an inline table with working directory to run it. The command is run in
+    // a subshell so that `cd` does not affect following commands.
+    // e.g. ["cargo fmt -- --check", { command = "cargo test", dir = "tests/integration" }]
+    fn commands(&self, key: &str, value: &toml::Value) -> Result<Vec<String>> {
+        let elems = match value {
+            toml::Value::Array(elems) => elems,
+            _ => return Err(self.invalid(format!("'{}' must be an array of commands", key))),
+        };
+        elems
+            .iter()
+            .map(|elem| match elem {
+                toml::Value::String(cmd) => Ok(cmd.clone()),
+                toml::Value::Table(table) => self.command_in_dir(key, table),
+                _ => Err(self.invalid(format!(
+                    "Command in '{}' must be a string or an inline table",
+                    key,
+                ))),
+            })
+            .collect()
+    }
+
+    fn command_in_dir(&self, key: &str, table: &toml::Table) -> Result<String> {
+        let mut command = None;
+        let mut dir = None;
+        for (k, v) in table {
+            match (k.join(".").as_str(), v) {
+                ("command", toml::Value::String(s)) => command = Some(s),
+                ("dir", toml::Value::String(s)) => dir = Some(s),
+                (k, _) => {
+                    return Err(self.invalid(format!(
+                        "Invalid key '{}' in command of '{}'. 'command' and 'dir' must be strings",
+                        k, key,
+                    )))
+                }
+            }
+        }
+        let command = match command {
+            Some(command) => command,
+            None => return Err(self.invalid(format!("'command' is missing in '{}'", key))),
+        };
+        Ok(match dir {
+            Some(dir) => format!("(cd {} && {})", shell_quote(dir), command),
+            None => command.clone(),
+        })
+    }
+
+    // Commands for each hook in `hooks` table. e.g. hooks.pre-commit = ["cargo test"]
+    fn hooks(&self) -> Result<Vec<(String, Vec<String>)>> {
+        let mut hooks = vec![];
+        for (key, value) in &self.table {
+            if key.len() != 2 || key[0] != "hooks" {
+                continue;
+            }
+            let hook = &key[1];
+            if hook.is_empty() || !hook.chars().all(|c| c.is_ascii_lowercase() || c == '-') {
+                return Err(self.invalid(format!("Invalid hook name {:?}", hook)));
+            }
+            let commands = self.commands(&key.join("."), value)?;
+            hooks.push((hook.clone(), commands));
+        }
+        Ok(hooks)
+    }
+
+    // Commands for each hook on branches matching the pattern in `branches` table. Patterns are glob
+    // patterns of `case` statement of shell. e.g. branches."release/*".pre-commit = ["cargo test"]
+    fn branches(&self) -> Result<Vec<(String, String, Vec<String>)>> {
+        let mut branches = vec![];
+        for (key, value) in &self.table {
+            if key.len() != 3 || key[0] != "branches" {
+                continue;
+            }
+            let (pattern, hook) = (&key[1], &key[2]);
+            let is_valid = !pattern.is_empty()
+                && pattern
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./*?".contains(c));
+            if !is_valid {
+                return Err(self.invalid(format!(
+                    "Invalid branch pattern {:?}. Only alphanumerics, '-', '_', '.', '/', '*' and '?' are allowed",
+                    pattern,
+                )));
+            }
+            if hook.is_empty() || !hook.chars().all(|c| c.is_ascii_lowercase() || c == '-') {
+                return Err(self.invalid(format!("Invalid hook name {:?}", hook)));
+            }
+            let commands = self.commands(&key.join("."), value)?;
+            branches.push((pattern.clone(), hook.clone(), commands));
+        }
+        Ok(branches)
+    }
+
+    // `skip = true` opts out of installing hooks. e.g. a repository where hooks are managed by other tool
+    fn skip(&self) -> Result<bool> {
+        match self
+            .table
+            .iter()
+            .find(|(k, _)| k.len() == 1 && k[0] == "skip")
+        {
+            Some((_, toml::Value::Bool(b))) => Ok(*b),
+            Some(..) => Err(self.invalid("'skip' must be a boolean".to_string())),
+            None => Ok(false),
+        }
+    }
+
+    // Keys of built-in commands assigned to the hook. e.g. prepush-commands = ["test"]
+    fn assigned_commands(&self, hook: &str) -> Result<Option<Vec<String>>> {
+        let name = format!("{}-commands", hook.replace('-', ""));
+        let value = match self
+            .table
+            .iter()
+            .find(|(k, _)| k.len() == 1 && k[0] == name)
+        {
+            Some((_, value)) => value,
+            None => return Ok(None),
+        };
+        let keys = self.strings(&name, value)?;
+        let cmds = cargo_commands();
+        for key in &keys {
+            if cmds.iter().all(|c| c.key != key) {
+                let known = cmds.iter().map(|c| c.key).collect::<Vec<_>>().join(", ");
+                return Err(self.invalid(format!(
+                    "Unknown command {:?} in '{}'. Available commands are: {}",
+                    key, name, known,
+                )));
+            }
+        }
+        Ok(Some(keys))
+    }
+}
+
+// Run commands for the current branch. Commands in `hooks` table are run on other branches and on
+// detached HEAD, where `git symbolic-ref` outputs nothing. `git rev-parse --abbrev-ref HEAD` is not
+// used since it fails on a branch without commits
+fn branches_script(default: &str, branches: &[(&str, String)]) -> String {
+    let mut s = "
+cargo_husky_branch=\"$(git symbolic-ref --short -q HEAD)\"\ncase \"$cargo_husky_branch\" in\n    '')".to_string();
+    s.push_str(default);
+    s.push_str("\n        ;;\n");
+    for (pattern, script) in branches {
+        s.push_str(&format!("    {})", pattern));
+        s.push_str(script);
+        s.push_str("\n        ;;\n");
+    }
+    s.push_str("    *)");
+    s.push_str(default);
+    s.push_str("\n        ;;\nesac");
+    s
+}
+
+fn install_configured_hooks(
+    hooks: &[(String, Vec<String>)],
+    branches: &[(String, String, Vec<String>)],
+) -> Result<Report> {
+    let script_of =
+        |commands: &[String]| commands_script(commands.iter().map(|c| command_script(c)));
+    let mut names: Vec<&str> = hooks.iter().map(|(h, _)| h.as_str()).collect();
+    for (_, hook, _) in branches {
+        if !names.contains(&hook.as_str()) {
+            names.push(hook);
+        }
+    }
+
+    let mut report = Report::default();
+    for hook in &names {
+        let default = hooks
+            .iter()
+            .find(|(h, _)| h == hook)
+            .map(|(_, commands)| script_of(commands))
+            .unwrap_or_default();
+        let on_branches: Vec<(&str, String)> = branches
+            .iter()
+            .filter(|(_, h, _)| h == hook)
+            .map(|(pattern, _, commands)| (pattern.as_str(), script_of(commands)))
+            .collect();
+        let script = if on_branches.is_empty() {
+            default
+        } else {
+            branches_script(&default, &on_branches)
+        };
+        report.add(hook, install_hook(hook, &script));
+    }
+    for &(hook, _) in HOOKS {
+        if !names.contains(&hook) {
+            report.add_failure(hook, uninstall_hook(hook));
+        }
+    }
+    Ok(report)
+}
+
+// Tell the directory where hooks were installed to tooling. It is available as
+// `cargo_husky::hooks_dir()`. Empty value is set when no hook was installed so that the same variable
+// in the environment of cargo is not used
+fn report_hooks_dir(installed: bool) -> Result<()> {
+    let dir = if installed {
+        let dir = resolve_hooks_dir()?;
+        verbose!("Hooks directory is {}", dir.display());
+        dir.display().to_string()
+    } else {
+        String::new()
+    };
+    println!("cargo:rustc-env=CARGO_HUSKY_INSTALLED_HOOKS_DIR={}", dir);
+    Ok(())
+}
+
+// Hooks generated by cargo-husky and whether the feature for each of them is enabled
+const HOOKS: &[(&str, bool)] = &[
+    ("pre-push", cfg!(feature = "prepush-hook")),
+    ("pre-commit", cfg!(feature = "precommit-hook")),
+    ("post-merge", cfg!(feature = "postmerge-hook")),
+    ("commit-msg", cfg!(feature = "commit-msg-hook")),
+    ("post-checkout", cfg!(feature = "postcheckout-hook")),
+    ("post-commit", cfg!(feature = "postcommit-hook")),
+    ("pre-rebase", cfg!(feature = "prerebase-hook")),
+    ("pre-auto-gc", cfg!(feature = "preautogc-hook")),
+    (
+        "prepare-commit-msg",
+        cfg!(feature = "prepare-commit-msg-hook"),
+    ),
+];
+
+// Environment variables set by common CI services. Installing hooks in CI is pointless and may fail
+// in read-only checkouts. test/test.rs has the same list, which must be kept in sync
+const CI_ENV_VARS: &[&str] = &[
+    "CI",
+    "CONTINUOUS_INTEGRATION",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "TRAVIS",
+    "CIRCLECI",
+    "APPVEYOR",
+    "BUILDKITE",
+    "DRONE",
+    "TF_BUILD",
+    "JENKINS_URL",
+    "TEAMCITY_VERSION",
+    "BITBUCKET_BUILD_NUMBER",
+];
+
+// Variable which tells the build is run in CI. Values such as `CI=false` are not regarded as CI
+fn detect_ci() -> Option<&'static str> {
+    CI_ENV_VARS.iter().cloned().find(|var| match env::var(var) {
+        Ok(v) => !matches!(v.trim(), "" | "0" | "false"),
+        Err(..) => false,
+    })
+}
+
+// Returns whether hooks were installed
+fn install() -> Result<bool> {
+    if let Some(var) = detect_ci() {
+        if !cfg!(feature = "install-in-ci") {
+            println!(
+                "cargo:warning=Skipped installing hooks in CI detected by ${}. Enable install-in-ci feature to install them",
+                var,
+            );
+            return Ok(false);
+        }
+        verbose!("Installing hooks in CI since install-in-ci feature is enabled");
+    }
+    let config = match Config::read() {
+        Ok(config) => config,
+        // User hooks do not use the configuration except for 'skip'. Broken configuration should not
+        // stop installing them
+        Err(err) if cfg!(feature = "user-hooks") => {
+            println!(
+                "cargo:warning=Ignored configuration since it cannot be read: {:?}",
+                err
+            );
+            None
+        }
+        Err(err) => return Err(err),
+    };
+    if let Some(config) = &config {
+        if config.skip()? {
+            verbose!(
+                "Skipped installing hooks since 'skip' is set in {}",
+                config.path.display(),
+            );
+            return Ok(false);
+        }
+    }
+    setup_githooks_dir()?;
+    if cfg!(feature = "user-hooks") {
+        verbose!("Installing user hooks since user-hooks feature is enabled");
+        return install_user_hooks()?.finish();
+    }
+    if let Some(config) = &config {
+        let hooks = config.hooks()?;
+        let branches = config.branches()?;
+        if !hooks.is_empty() || !branches.is_empty() {
+            verbose!("Installing hooks configured in {}", config.path.display());
+            return install_configured_hooks(&hooks, &branches)?.finish();
+        }
+    }
+    verbose!("Installing hooks enabled by features");
+    let mut report = Report::default();
+    for &(hook, enabled) in HOOKS {
+        if enabled {
+            let assigned = match &config {
+                Some(config) => config.assigned_commands(hook)?,
+                None => None,
+            };
+            let result = feature_script(hook, assigned.as_deref())
+                .and_then(|script| install_hook(hook, &script));
+            report.add(hook, result);
+        } else {
+            // Clean up the hook generated while the feature was enabled
+            report.add_failure(hook, uninstall_hook(hook));
+        }
+    }
+    if report.is_empty() {
+        warn_no_hook_feature();
+    }
+    report.finish()
+}
+
+// Adding cargo-husky without any hook feature silently does nothing, which is a common confusion
+fn warn_no_hook_feature() {
+    let features = HOOKS
+        .iter()
+        .map(|&(hook, _)| hook_feature(hook))
+        .chain(Some("user-hooks".to_string()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!(
+        "cargo:warning=No hook was installed since no hook feature is enabled. Enable one of features {} or configure 'hooks' in [package.metadata.cargo-husky]",
+        features,
+    );
+}
+
+// Name of the feature to generate the hook. `-` is removed except for hooks of commit message
+// (e.g. prepush-hook, commit-msg-hook)
+fn hook_feature(hook: &str) -> String {
+    if hook.ends_with("commit-msg") {
+        format!("{}-hook", hook)
+    } else {
+        format!("{}-hook", hook.replace('-', ""))
+    }
+}
+
+fn main() -> Result<()> {
+    if var_os("CARGO_HUSKY_DONT_INSTALL_HOOKS").is_some() {
+        eprintln!("Warning: Found '$CARGO_HUSKY_DONT_INSTALL_HOOKS' in env, not doing anything!");
+        return report_hooks_dir(false);
+    }
+
+    let installed = match install() {
+        Ok(installed) => installed,
+        Err(e) if e.is_warning() => {
+            eprintln!("Warning: {:?}", e);
+            matches!(e, Error::HooksFailed(_, true))
+        }
+        Err(e) => return Err(e),
+    };
+    if installed {
+        warn_submodule();
+        // Hooks in the main location are already installed. Failing to mirror them to worktrees
+        // should not break the build
+        if let Err(e) = install_worktree_hooks() {
+            eprintln!("Warning: {:?}", e);
+        }
+    }
+    report_hooks_dir(installed)
+}
+
+// Minimal TOML reader to read cargo-husky configuration. It does not validate the whole document
+// strictly since the file is also read by cargo.
+mod toml {
+    use std::iter::Peekable;
+    use std::str::Chars;
+
+    #[derive(Debug)]
+    pub enum Value {
+        String(String),
+        Array(Vec<Value>),
+        Table(Table),
+        Bool(bool),
+        // Integers, floats and datetimes are not used by cargo-husky
+        Other,
+    }
+
+    // Keys are flattened into dotted paths in order of appearance
+    pub type Table = Vec<(Vec<String>, Value)>;
+
+    pub type Result<T> = ::std::result::Result<T, String>;
+
+    // Message of parse error and the key path where it occurred. The path is empty when the table
+    // is unknown
+    pub type Error = (Vec<String>, String);
+
+    struct Parser<'a> {
+        chars: Peekable<Chars<'a>>,
+        line: usize,
+        errors: Vec<Error>,
+    }
+
+    pub fn parse(src: &str) -> Result<Table> {
+        let (table, mut errors) = parse_lenient(src);
+        if errors.is_empty() {
+            Ok(table)
+        } else {
+            Err(errors.swap_remove(0).1)
+        }
+    }
+
+    // Parsing continues from the next line of an error so that callers can ignore errors in tables
+    // they do not read. Cargo.toml may use syntax this reader does not support
+    pub fn parse_lenient(src: &str) -> (Table, Vec<Error>) {
+        // UTF-8 BOM is accepted by cargo
+        let src = src.strip_prefix('\u{feff}').unwrap_or(src);
+        let mut p = Parser {
+            chars: src.chars().peekable(),
+            line: 1,
+            errors: vec![],
+        };
+        let table = p.document();
+        (table, p.errors)
+    }
+
+    fn push_flattened(table: &mut Table, path: Vec<String>, value: Value) {
+        match value {
+            Value::Table(entries) => {
+                for (key, value) in entries {
+                    let mut p = path.clone();
+                    p.extend(key);
+                    push_flattened(table, p, value);
+                }
+            }
+            value => table.push((path, value)),
+        }
+    }
+
+    impl<'a> Parser<'a> {
+        fn next(&mut self) -> Option<char> {
+            let c = self.chars.next();
+            if c == Some('\n') {
+                self.line += 1;
+            }
+            c
+        }
+
+        fn eat(&mut self, want: char) -> bool {
+            if self.chars.peek() == Some(&want) {
+                self.next();
+                true
+            } else {
+                false
+            }
+        }
+
+        fn expect(&mut self, want: char) -> Result<()> {
+            match self.next() {
+                Some(c) if c == want => Ok(()),
+                Some(c) => Err(format!("Expected {:?} but got {:?}", want, c)),
+                None => Err(format!("Expected {:?} but reached end of file", want)),
+            }
+        }
+
+        fn skip_spaces(&mut self) {
+            while self.eat(' ') || self.eat('\t') {}
+        }
+
+        fn skip_comment(&mut self) {
+            if self.chars.peek() == Some(&'#') {
+                while matches!(self.chars.peek(), Some(&c) if c != '\n') {
+                    self.next();
+                }
+            }
+        }
+
+        // Skip whitespaces, newlines and comments
+        fn skip_blank(&mut self) {
+            loop {
+                self.skip_spaces();
+                self.skip_comment();
+                if !self.eat('\n') && !self.eat('\r') {
+                    return;
+                }
+            }
+        }
+
+        fn end_of_line(&mut self) -> Result<()> {
+            self.skip_spaces();
+            self.skip_comment();
+            self.eat('\r');
+            match self.next() {
+                None | Some('\n') => Ok(()),
+                Some(c) => Err(format!("Unexpected character {:?}", c)),
+            }
+        }
+
+        fn document(&mut self) -> Table {
+            let mut table = vec![];
+            // None while in a table whose header is broken. Its entries are dropped
+            let mut prefix = Some(vec![]);
+            loop {
+                self.skip_blank();
+                let mut path = prefix.clone().unwrap_or_default();
+                let result = match self.chars.peek() {
+                    None => return table,
+                    Some(&'[') => {
+                        prefix = None;
+                        path.clear();
+                        self.header().map(|header| prefix = Some(header))
+                    }
+                    Some(_) => self.entry(&mut path).map(|value| {
+                        if prefix.is_some() {
+                            push_flattened(&mut table, path.clone(), value);
+                        }
+                    }),
+                };
+                if let Err(msg) = result {
+                    let line = self.line;
+                    self.errors
+                        .push((path, format!("{} at line {}", msg, line)));
+                    // Skip the rest of the line
+                    while self.line == line && self.next().is_some() {}
+                }
+            }
+        }
+
+        fn header(&mut self) -> Result<Vec<String>> {
+            self.expect('[')?;
+            let array_of_tables = self.eat('[');
+            self.skip_spaces();
+            let key = self.key()?;
+            self.expect(']')?;
+            if array_of_tables {
+                self.expect(']')?;
+            }
+            self.end_of_line()?;
+            Ok(key)
+        }
+
+        // The key is appended to the path as soon as it is parsed so that an error in the value is
+        // reported with the path
+        fn entry(&mut self, path: &mut Vec<String>) -> Result<Value> {
+            path.extend(self.key()?);
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            self.end_of_line()?;
+            Ok(value)
+        }
+
+        fn key_value(&mut self) -> Result<(Vec<String>, Value)> {
+            let key = self.key()?;
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            Ok((key, value))
+        }
+
+        // Dotted key such as `foo."bar".baz`. Spaces after the key are consumed
+        fn key(&mut self) -> Result<Vec<String>> {
+            let mut path = vec![];
+            loop {
+                let part = match self.chars.peek() {
+                    Some(&'"') => {
+                        self.next();
+                        self.basic_string()?
+                    }
+                    Some(&'\'') => {
+                        self.next();
+                        self.literal_string()?
+                    }
+                    _ => {
+                        let mut s = String::new();
+                        while let Some(&c) = self.chars.peek() {
+                            if !c.is_ascii_alphanumeric() && c != '-' && c != '_' {
+                                break;
+                            }
+                            s.push(c);
+                            self.next();
+                        }
+                        if s.is_empty() {
+                            return Err("Key is expected".to_string());
+                        }
+                        s
+                    }
+                };
+                path.push(part);
+                self.skip_spaces();
+                if !self.eat('.') {
+                    return Ok(path);
+                }
+                self.skip_spaces();
+            }
+        }
+
+        fn value(&mut self) -> Result<Value> {
+            match self.chars.peek() {
+                Some(&'"') => {
+                    self.next();
+                    if self.eat('"') {
+                        if self.eat('"') {
+                            self.multiline_string('"').map(Value::String)
+                        } else {
+                            Ok(Value::String(String::new()))
+                        }
+                    } else {
+                        self.basic_string().map(Value::String)
+                    }
+                }
+                Some(&'\'') => {
+                    self.next();
+                    if self.eat('\'') {
+                        if self.eat('\'') {
+                            self.multiline_string('\'').map(Value::String)
+                        } else {
+                            Ok(Value::String(String::new()))
+                        }
+                    } else {
+                        self.literal_string().map(Value::String)
+                    }
+                }
+                Some(&'[') => {
+                    self.next();
+                    self.array().map(Value::Array)
+                }
+                Some(&'{') => {
+                    self.next();
+                    self.inline_table().map(Value::Table)
+                }
+                _ => {
+                    let mut s = String::new();
+                    while let Some(&c) = self.chars.peek() {
+                        match c {
+                            ',' | ']' | '}' | '#' | '\r' | '\n' => break,
+                            _ => s.push(c),
+                        }
+                        self.next();
+                    }
+                    match s.trim_end() {
+                        "" => Err("Value is expected".to_string()),
+                        "true" => Ok(Value::Bool(true)),
+                        "false" => Ok(Value::Bool(false)),
+                        _ => Ok(Value::Other),
+                    }
+                }
+            }
+        }
+
+        fn array(&mut self) -> Result<Vec<Value>> {
+            let mut elems = vec![];
+            loop {
+                self.skip_blank();
+                if self.eat(']') {
+                    return Ok(elems);
+                }
+                elems.push(self.value()?);
+                self.skip_blank();
+                if self.eat(']') {
+                    return Ok(elems);
+                }
+                self.expect(',')?;
+            }
+        }
+
+        fn inline_table(&mut self) -> Result<Table> {
+            let mut table = vec![];
+            self.skip_spaces();
+            if self.eat('}') {
+                return Ok(table);
+            }
+            loop {
+                self.skip_spaces();
+                let (key, value) = self.key_value()?;
+                push_flattened(&mut table, key, value);
+                self.skip_spaces();
+                if self.eat('}') {
+                    return Ok(table);
+                }
+                self.expect(',')?;
+            }
+        }
+
+        fn escape(&mut self) -> Result<char> {
+            let c = match self.next() {
+                Some('b') => '\u{8}',
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some('f') => '\u{c}',
+                Some('r') => '\r',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some(u @ 'u') | Some(u @ 'U') => {
+                    let len = if u == 'u' { 4 } else { 8 };
+                    let mut code = String::new();
+                    for _ in 0..len {
+                        code.extend(self.next());
+                    }
+                    return u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(::std::char::from_u32)
+                        .ok_or_else(|| format!("Invalid unicode escape \\{}{}", u, code));
+                }
+                Some(c) => return Err(format!("Invalid escape sequence \\{}", c)),
+                None => return Err("Unterminated string".to_string()),
+            };
+            Ok(c)
+        }
+
+        // Opening quote was already consumed
+        fn basic_string(&mut self) -> Result<String> {
+            let mut s = String::new();
+            loop {
+                match self.next() {
+                    Some('"') => return Ok(s),
+                    Some('\\') => s.push(self.escape()?),
+                    Some('\n') | None => return Err("Unterminated string".to_string()),
+                    Some(c) => s.push(c),
+                }
+            }
+        }
+
+        // Opening quote was already consumed
+        fn literal_string(&mut self) -> Result<String> {
+            let mut s = String::new();
+            loop {
+                match self.next() {
+                    Some('\'') => return Ok(s),
+                    Some('\n') | None => return Err("Unterminated string".to_string()),
+                    Some(c) => s.push(c),
+                }
+            }
+        }
+
+        // Opening triple quotes were already consumed
+        fn multiline_string(&mut self, quote: char) -> Result<String> {
+            let mut s = String::new();
+            // A newline immediately following the opening delimiter is trimmed
+            self.eat('\r');
+            self.eat('\n');
+            loop {
+                match self.next() {
+                    Some(c) if c == quote => {
+                        let mut n = 1;
+                        while n < 5 && self.eat(quote) {
+                            n += 1;
+                        }
+                        if n >= 3 {
+                            // Up to 2 quotes are allowed just before the closing delimiter
+                            s.extend((3..n).map(|_| quote));
+                            return Ok(s);
+                        }
+                        s.extend((0..n).map(|_| quote));
+                    }
+                    Some('\\') if quote == '"' => {
+                        if matches!(self.chars.peek(), Some(c) if c.is_whitespace()) {
+                            // Line ending backslash trims all whitespaces until next non-whitespace
+                            while matches!(self.chars.peek(), Some(c) if c.is_whitespace()) {
+                                self.next();
+                            }
+                        } else {
+                            s.push(self.escape()?);
+                        }
+                    }
+                    Some(c) => s.push(c),
+                    None => return Err("Unterminated string".to_string()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Empty directory for each test in the temporary directory
+    fn tmpdir_for(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join("cargo-husky-build-test").join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    // Directory where cargo would put OUT_DIR of cargo-husky in the repository
+    fn out_dir_in(root: &Path) -> PathBuf {
+        let dir = root.join("target").join("debug").join("build").join("out");
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn find_git_directory() {
+        let root = tmpdir_for("git-directory");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let gitdir = find_gitdir_in(&out_dir_in(&root), None).unwrap();
+        assert_eq!(gitdir, root.join(".git"));
+    }
+
+    #[test]
+    fn find_common_directory_of_worktree() {
+        let root = tmpdir_for("worktree");
+        let main = root.join("main");
+        fs::create_dir_all(main.join(".git").join("worktrees").join("wt")).unwrap();
+        let wt = root.join("wt");
+        fs::create_dir_all(&wt).unwrap();
+        fs::write(wt.join(".git"), "gitdir: ../main/.git/worktrees/wt\n").unwrap();
+
+        let gitdir = find_gitdir_in(&out_dir_in(&wt), None).unwrap();
+        assert_eq!(
+            fs::canonicalize(gitdir).unwrap(),
+            fs::canonicalize(main.join(".git")).unwrap(),
+        );
+    }
+
+    #[test]
+    fn find_git_directory_of_submodule() {
+        let root = tmpdir_for("submodule");
+        let modules = root.join(".git").join("modules").join("sub");
+        fs::create_dir_all(&modules).unwrap();
+        fs::write(root.join(".git").join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        let sub = root.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join(".git"), "gitdir: ../.git/modules/sub\r\n").unwrap();
+
+        let gitdir = find_gitdir_in(&out_dir_in(&sub), None).unwrap();
+        let gitdir = fs::canonicalize(gitdir).unwrap();
+        assert_eq!(gitdir, fs::canonicalize(modules).unwrap());
+        assert!(is_submodule_gitdir(&gitdir));
+    }
+
+    #[test]
+    fn find_bare_repository() {
+        let root = tmpdir_for("bare");
+        fs::create_dir_all(root.join("hooks")).unwrap();
+        fs::write(root.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        let gitdir = find_gitdir_in(&out_dir_in(&root), None).unwrap();
+        assert_eq!(gitdir, root);
+    }
+
+    #[test]
+    fn broken_git_file() {
+        let root = tmpdir_for("broken-git-file");
+        fs::write(root.join(".git"), "gitdir: ../moved/.git/worktrees/wt\n").unwrap();
+        let err = find_gitdir_in(&out_dir_in(&root), None).unwrap_err();
+        assert!(matches!(err, Error::GitDirFileBroken(..)), "{:?}", err);
+
+        fs::write(root.join(".git"), "gitdir:\n").unwrap();
+        let err = find_gitdir_in(&out_dir_in(&root), None).unwrap_err();
+        assert!(matches!(err, Error::GitDirNotFound), "{:?}", err);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn broken_git_symlink() {
+        let root = tmpdir_for("broken-git-symlink");
+        std::os::unix::fs::symlink(root.join("nowhere"), root.join(".git")).unwrap();
+        let err = find_gitdir_in(&out_dir_in(&root), None).unwrap_err();
+        assert!(matches!(err, Error::BrokenGitDirLink(..)), "{:?}", err);
+    }
+
+    #[test]
+    fn find_git_directory_from_fallback() {
+        let root = tmpdir_for("fallback");
+        let repo = root.join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        let out_dir = out_dir_in(&root.join("outside"));
+
+        let err = find_gitdir_in(&out_dir, None).unwrap_err();
+        assert!(matches!(err, Error::GitDirNotFound), "{:?}", err);
+        // Manifest is necessary at the root of the repository
+        let vendor = repo.join("vendor");
+        let err = find_gitdir_in(&out_dir, Some(&vendor)).unwrap_err();
+        assert!(matches!(err, Error::UnrelatedGitDir(..)), "{:?}", err);
+        fs::write(repo.join("Cargo.toml"), "[workspace]\n").unwrap();
+        let gitdir = find_gitdir_in(&out_dir, Some(&vendor)).unwrap();
+        assert_eq!(gitdir, repo.join(".git"));
+
+        // Repository of cargo-husky itself when it is a path dependency outside the repository
+        let checkout = root.join("cargo-husky");
+        fs::create_dir_all(checkout.join(".git")).unwrap();
+        fs::write(checkout.join("Cargo.toml"), "[package]\n").unwrap();
+        let err = find_gitdir_in(&out_dir, Some(&checkout)).unwrap_err();
+        assert!(matches!(err, Error::UnrelatedGitDir(..)), "{:?}", err);
+    }
+
+    fn assert_valid_sh(script: &str) {
+        if let Err(err) = check_posix_script(script.as_bytes()) {
+            panic!("{:?}\n{}", err, script);
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn generated_hooks_are_valid_sh() {
+        // All commands are assigned so that they are generated regardless of features
+        let keys: Vec<String> = cargo_commands().iter().map(|c| c.key.to_string()).collect();
+        for &(hook, _) in HOOKS {
+            // Hooks of commit message need the command set by environment variables
+            if hook.ends_with("commit-msg") {
+                continue;
+            }
+            let mut content = vec![];
+            let script = feature_script(hook, Some(&keys)).unwrap();
+            write_script(&mut content, hook, &script).unwrap();
+            assert_valid_sh(std::str::from_utf8(&content).unwrap());
+        }
+    }
+
+    // Skipped when shellcheck is not installed
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn check_hooks_with_shellcheck() {
+        if Command::new("shellcheck")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            eprintln!("Skipped since shellcheck is not installed");
+            return;
+        }
+        let keys: Vec<String> = cargo_commands().iter().map(|c| c.key.to_string()).collect();
+        let mut content = vec![];
+        let script = feature_script("pre-push", Some(&keys)).unwrap();
+        write_script(&mut content, "pre-push", &script).unwrap();
+        if let Err(err) = run_script_checker(
+            "shellcheck",
+            &["--shell=sh", "--severity=error", "-"],
+            &content,
+        ) {
+            panic!("{:?}\n{}", err, String::from_utf8_lossy(&content));
+        }
+
+        // Valid for `sh -n`, but shellcheck reports that a space is missing before `]`
+        let err = check_posix_script(b"#!/bin/sh\nif [ -n \"$1\"]; then\n    echo yes\nfi\n")
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidHookScript("shellcheck", _)),
+            "{:?}",
+            err
+        );
+    }
+
+    // Snippets of features which are not enabled in this test are checked one by one
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn feature_snippets_are_valid_sh() {
+        for snippet in [
+            SKIP_GUARD,
+            PREPUSH_PRELUDE,
+            DEPS_CHANGED_GUARD,
+            REBASE_GUARD,
+            DOTENV_SCRIPT,
+            FIX_PATH_SCRIPT,
+            TEST_FILTERS_SCRIPT,
+            FAILURES_CHECK,
+        ] {
+            assert_valid_sh(snippet);
+        }
+        assert_valid_sh(&fix_and_stage_script("cargo fmt"));
+        assert_valid_sh(&staged_files_script("rustfmt --check", Some("hint")));
+        assert_valid_sh(&changed_tests_script("cargo test", Some("hint"), 1));
+        assert_valid_sh(&command_script_with_retries("cargo test", Some("hint"), 2));
+        assert_valid_sh(&report_record_script("echo \"it's\""));
+        assert_valid_sh(&branches_script(
+            &command_script("echo default"),
+            &[("release/*", command_script("echo release"))],
+        ));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn reject_invalid_script() {
+        let err = check_posix_script(b"if true; then\n    echo 'unterminated'\n").unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidHookScript("sh", _)),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn detect_not_writable_error() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(is_not_writable(&denied));
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        assert!(!is_not_writable(&not_found));
+        if cfg!(not(target_os = "windows")) {
+            // EROFS
+            assert!(is_not_writable(&io::Error::from_raw_os_error(30)));
+        }
+    }
+
+    #[test]
+    fn parse_max_file_size() {
+        assert_eq!(parse_file_size("1048576"), Some(1048576));
+        assert_eq!(parse_file_size(" 512K "), Some(512 * 1024));
+        assert_eq!(parse_file_size("5M"), Some(5 * 1024 * 1024));
+        assert_eq!(parse_file_size("1g"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_file_size(""), None);
+        assert_eq!(parse_file_size("M"), None);
+        assert_eq!(parse_file_size("-1"), None);
+        assert_eq!(parse_file_size("1.5M"), None);
+        assert_eq!(parse_file_size("5MB"), None);
+        assert_eq!(parse_file_size("99999999999999999999G"), None);
+    }
+
+    // Flattened entries of the TOML document formatted as `key = value` for comparison
+    fn parse_toml(src: &str) -> Vec<String> {
+        toml::parse(src)
+            .unwrap()
+            .iter()
+            .map(|(key, value)| format!("{} = {:?}", key.join("."), value))
+            .collect()
+    }
+
+    #[test]
+    fn parse_toml_strings() {
+        let src = r##"
+basic = "cargo test"
+literal = 'C:\path\to'
+empty-basic = ""
+empty-literal = ''
+escapes = "tab\tnewline\nquote\"backslash\\"
+unicode = "\u00e9\U0001F980"
+hash = "# not a comment" # comment
+"##;
+        assert_eq!(
+            parse_toml(src),
+            [
+                r#"basic = String("cargo test")"#,
+                r#"literal = String("C:\\path\\to")"#,
+                r#"empty-basic = String("")"#,
+                r#"empty-literal = String("")"#,
+                r#"escapes = String("tab\tnewline\nquote\"backslash\\")"#,
+                r#"unicode = String("é🦀")"#,
+                r##"hash = String("# not a comment")"##,
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_toml_multiline_strings() {
+        let src = "basic = \"\"\"\nfirst\n  second\"\"\"\ntrimmed = \"\"\"\\\n    one \\\n    two\"\"\"\nquotes = \"\"\"\"a\"\"\"\"\"\nliteral = '''\n\\n is not escaped\n'''\n";
+        assert_eq!(
+            parse_toml(src),
+            [
+                r#"basic = String("first\n  second")"#,
+                r#"trimmed = String("one two")"#,
+                r#"quotes = String("\"a\"\"")"#,
+                r#"literal = String("\\n is not escaped\n")"#,
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_toml_arrays_and_inline_tables() {
+        let src = r#"
+commands = [
+    "cargo fmt", # formatting
+    # comment line
+    { command = "cargo test", dir = "sub" },
+    [true, false],
+]
+empty = [ ]
+table = { a.b = "c", d = 1 }
+other = 2024-01-01
+"#;
+        assert_eq!(
+            parse_toml(src),
+            [
+                r#"commands = Array([String("cargo fmt"), Table([(["command"], String("cargo test")), (["dir"], String("sub"))]), Array([Bool(true), Bool(false)])])"#,
+                "empty = Array([])",
+                r#"table.a.b = String("c")"#,
+                "table.d = Other",
+                "other = Other",
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_toml_keys_and_tables() {
+        let src = r#"
+[package]
+name = "foo"
+[package.metadata.cargo-husky]
+hooks."pre-commit" = ["cargo check"]
+'literal key' . bare_key = true
+[[bin]]
+name = "bar"
+"#;
+        assert_eq!(
+            parse_toml(src),
+            [
+                r#"package.name = String("foo")"#,
+                r#"package.metadata.cargo-husky.hooks.pre-commit = Array([String("cargo check")])"#,
+                "package.metadata.cargo-husky.literal key.bare_key = Bool(true)",
+                r#"bin.name = String("bar")"#,
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_toml_crlf_and_bom() {
+        let src =
+            "\u{feff}[package]\r\nname = \"foo\" # comment\r\n\r\ndesc = \"\"\"\r\nbar\"\"\"\r\n";
+        assert_eq!(
+            parse_toml(src),
+            [
+                r#"package.name = String("foo")"#,
+                r#"package.desc = String("bar")"#
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_toml_errors() {
+        for (src, msg) in &[
+            ("= 1", "Key is expected at line 1"),
+            ("a = 1\nb", "Expected '=' but reached end of file at line 2"),
+            ("a = \"foo\nb = 1", "Unterminated string at line 2"),
+            (
+                "a = 1\n\nb = [\"x\" \"y\"]",
+                "Expected ',' but got '\"' at line 3",
+            ),
+            ("a = \"\\x\"", "Invalid escape sequence \\x at line 1"),
+            (
+                "a = \"\\uD800\"",
+                "Invalid unicode escape \\uD800 at line 1",
+            ),
+            ("[a\nb = 1", "Expected ']' but got '\\n' at line 2"),
+            ("a = 'x' b", "Unexpected character 'b' at line 1"),
+            ("a = '''foo", "Unterminated string at line 1"),
+        ] {
+            assert_eq!(toml::parse(src).unwrap_err(), *msg, "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn parse_toml_leniently() {
+        let src = "[package]\nname = 'foo'\nbroken = { a = 1,\n  b = 2 }\n[package.metadata.cargo-husky]\nhooks.pre-push = ['cargo test']\nbad = [\n";
+        let (table, errors) = toml::parse_lenient(src);
+        let keys: Vec<_> = table.iter().map(|(k, _)| k.join(".")).collect();
+        assert_eq!(
+            keys,
+            [
+                "package.name",
+                "package.metadata.cargo-husky.hooks.pre-push"
+            ],
+        );
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|(k, msg)| format!("{}: {}", k.join("."), msg))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "package.broken: Key is expected at line 3",
+                "package.b: Unexpected character '}' at line 4",
+                "package.metadata.cargo-husky.bad: Value is expected at line 8",
+            ],
+        );
+    }
+
+    #[test]
+    fn escape_json_string() {
+        assert_eq!(json_string("cargo test"), r#""cargo test""#);
+        assert_eq!(json_string(r#"echo "a\b""#), r#""echo \"a\\b\"""#);
+        assert_eq!(json_string("a\tb\nc\u{1}"), r#""a\tb\nc\u0001""#);
+        assert_eq!(json_string("echo 'ü'"), r#""echo 'ü'""#);
     }
 }
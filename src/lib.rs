@@ -1 +1,9 @@
-
+/// Returns the directory where cargo-husky installed Git hooks while building this crate. `None` is
+/// returned when no hook was installed (e.g. `.git` directory was not found or installation was
+/// skipped).
+pub fn hooks_dir() -> Option<&'static str> {
+    match option_env!("CARGO_HUSKY_INSTALLED_HOOKS_DIR") {
+        Some("") | None => None,
+        dir => dir,
+    }
+}
@@ -0,0 +1,2 @@
+// Unit tests of functions in the build script. The build script is compiled as a test crate as-is
+include!("../build.rs");
@@ -7,7 +7,7 @@ use semver::Version as SemVer;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Output};
+use std::process::{Command, Output, Stdio};
 use std::{env, ffi, fs, str, thread, time};
 
 lazy_static! {
@@ -54,20 +54,56 @@ fn tmpdir_for(name: &str) -> PathBuf {
 
 fn open_cargo_toml(repo_dir: &Path) -> fs::File {
     OpenOptions::new()
-        .write(true)
         .append(true)
         .open(repo_dir.join("Cargo.toml"))
         .unwrap()
 }
 
-fn run_cargo<'a, I, S, P>(project_root: P, args: I) -> Result<Output, String>
+// Variables removed from the environment of cargo so that tests run in CI install hooks. This list
+// must be kept in sync with CI_ENV_VARS in build.rs
+const CI_ENV_VARS: &[&str] = &[
+    "CI",
+    "CONTINUOUS_INTEGRATION",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "TRAVIS",
+    "CIRCLECI",
+    "APPVEYOR",
+    "BUILDKITE",
+    "DRONE",
+    "TF_BUILD",
+    "JENKINS_URL",
+    "TEAMCITY_VERSION",
+    "BITBUCKET_BUILD_NUMBER",
+];
+
+fn run_cargo<I, S, P>(project_root: P, args: I) -> Result<Output, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<ffi::OsStr>,
+    P: AsRef<Path>,
+{
+    run_cargo_with_env(project_root, args, &[])
+}
+
+fn run_cargo_with_env<I, S, P>(
+    project_root: P,
+    args: I,
+    envs: &[(&str, &str)],
+) -> Result<Output, String>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<ffi::OsStr>,
     P: AsRef<Path>,
 {
-    let out = Command::new("cargo")
+    let mut cmd = Command::new("cargo");
+    // Hooks are not installed in CI by default. Tests may be run in CI
+    for var in CI_ENV_VARS {
+        cmd.env_remove(var);
+    }
+    let out = cmd
         .args(args)
+        .envs(envs.iter().cloned())
         .current_dir(&project_root)
         .output()
         .unwrap();
@@ -80,7 +116,7 @@ where
 
 fn cargo_project_for(name: &str) -> PathBuf {
     let dir = tmpdir_for(name);
-    run_cargo(&dir, &["init", "--lib"]).unwrap();
+    run_cargo(&dir, ["init", "--lib"]).unwrap();
 
     let mut cargo_toml = open_cargo_toml(&dir);
     writeln!(
@@ -105,17 +141,35 @@ fn hook_path(root: &Path, name: &str) -> PathBuf {
     path.push("hooks");
     assert!(path.exists()); // hooks directory should always exist
     path.push(name);
-    return path;
+    path
 }
 
 fn get_hook_script(root: &Path, hook: &str) -> Option<String> {
     let path = hook_path(root, hook);
-    let mut f = File::open(path).ok()?;
+    let mut f = File::open(&path).ok()?;
     let mut s = String::new();
     f.read_to_string(&mut s).unwrap();
+    // Every hook generated in tests is checked so that each combination of features is covered.
+    // Hooks rewritten with CRLF by tests are not generated ones
+    if cfg!(not(target_os = "windows"))
+        && s.contains("# This hook was set by cargo-husky")
+        && !s.contains('\r')
+    {
+        assert_valid_sh(&path);
+    }
     Some(s)
 }
 
+fn assert_valid_sh(path: &Path) {
+    let out = Command::new("sh").arg("-n").arg(path).output().unwrap();
+    assert!(
+        out.status.success(),
+        "{:?} is not valid sh: {}",
+        path,
+        String::from_utf8_lossy(&out.stderr),
+    );
+}
+
 fn decrease_patch(mut ver: SemVer) -> SemVer {
     if ver.patch > 0 {
         ver.patch -= 1;
@@ -137,10 +191,10 @@ fn decrease_patch(mut ver: SemVer) -> SemVer {
 #[test]
 fn default_behavior() {
     let root = cargo_project_for("default");
-    run_cargo(&root, &["test"]).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
     let script = get_hook_script(&root, "pre-push").unwrap();
 
-    assert_eq!(script.lines().nth(0).unwrap(), "#!/bin/sh");
+    assert_eq!(script.lines().next().unwrap(), "#!/bin/sh");
     assert!(script
         .lines()
         .nth(2)
@@ -161,7 +215,7 @@ fn hook_file_is_executable() {
     use std::os::unix::fs::PermissionsExt;
 
     let root = cargo_project_for("unit-permission");
-    run_cargo(&root, &["test"]).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
     let prepush_path = hook_path(&root, "pre-push");
     let mode = File::open(&prepush_path)
@@ -181,7 +235,7 @@ fn change_features() {
         cargo_toml,
         "default-features = false\nfeatures = [\"precommit-hook\", \"run-cargo-clippy\", \"run-cargo-check\", \"run-cargo-fmt\"]"
     ).unwrap();
-    run_cargo(&root, &["test"]).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
     assert_eq!(get_hook_script(&root, "pre-push"), None);
 
@@ -190,14 +244,23 @@ fn change_features() {
     assert_eq!(
         script
             .lines()
+            .map(str::trim_start)
             .filter(|l| *l == "cargo clippy -- -D warnings")
             .count(),
         1
     );
-    assert_eq!(script.lines().filter(|l| *l == "cargo check").count(), 1);
     assert_eq!(
         script
             .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo check")
+            .count(),
+        1
+    );
+    assert_eq!(
+        script
+            .lines()
+            .map(str::trim_start)
             .filter(|l| *l == "cargo fmt -- --check")
             .count(),
         1
@@ -212,29 +275,39 @@ fn change_features_using_run_for_all() {
         cargo_toml,
         "default-features = false\nfeatures = [\"precommit-hook\", \"run-for-all\", \"run-cargo-test\", \"run-cargo-check\", \"run-cargo-clippy\", \"run-cargo-fmt\"]"
     ).unwrap();
-    run_cargo(&root, &["test"]).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
     assert_eq!(get_hook_script(&root, "pre-push"), None);
 
     let script = get_hook_script(&root, "pre-commit").unwrap();
     assert_eq!(
-        script.lines().filter(|l| *l == "cargo test --all").count(),
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo test --all")
+            .count(),
         1
     );
     assert_eq!(
         script
             .lines()
+            .map(str::trim_start)
             .filter(|l| *l == "cargo clippy --all -- -D warnings")
             .count(),
         1
     );
     assert_eq!(
-        script.lines().filter(|l| *l == "cargo check --all").count(),
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo check --all")
+            .count(),
         1
     );
     assert_eq!(
         script
             .lines()
+            .map(str::trim_start)
             .filter(|l| *l == "cargo fmt --all -- --check")
             .count(),
         1
@@ -242,296 +315,4570 @@ fn change_features_using_run_for_all() {
 }
 
 #[test]
-fn hook_not_updated_twice() {
-    let root = cargo_project_for("not-update-twice");
-    run_cargo(&root, &["test"]).unwrap();
+fn postcheckout_hook() {
+    let root = cargo_project_for("postcheckout-hook");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"postcheckout-hook\", \"run-cargo-check\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
-    let prepush_path = hook_path(&root, "pre-push");
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
 
-    let first = File::open(&prepush_path)
-        .unwrap()
-        .metadata()
+    let script = get_hook_script(&root, "post-checkout").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
         .unwrap()
-        .modified()
-        .unwrap();
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    assert_eq!(script.lines().filter(|l| *l == "cargo check").count(), 1);
+}
 
-    // Remove 'target' directory to trigger compiling the package again.
-    // When package is updated, the package is re-compiled. But here, package itself is not updated.
-    // .git/hooks/pre-push was directly modified. So manually triggering re-compilation is necessary.
-    fs::remove_dir_all(root.join("target")).unwrap();
+#[test]
+fn postcommit_hook() {
+    let root = cargo_project_for("postcommit-hook");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"postcommit-hook\", \"run-cargo-test\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
-    // Ensure modified time differs from previous
-    thread::sleep(time::Duration::from_secs(1));
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
 
-    run_cargo(&root, &["test"]).unwrap();
-    let second = File::open(&prepush_path)
-        .unwrap()
-        .metadata()
+    let script = get_hook_script(&root, "post-commit").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
         .unwrap()
-        .modified()
-        .unwrap();
-
-    assert_eq!(first, second); // Check the second `cargo test` does not modify hook script
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    assert_eq!(script.lines().filter(|l| *l == "cargo test").count(), 1);
 }
 
 #[test]
-fn regenerate_hook_script_on_package_update() {
-    let root = cargo_project_for("package-update");
+fn exclude_packages_from_workspace_commands() {
+    let root = cargo_project_for("exclude-packages");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-fmt\", \"run-cargo-clippy\", \"run-cargo-check\", \"run-cargo-test\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_EXCLUDE", "generated, vendored-crate,")],
+    )
+    .unwrap();
 
-    run_cargo(&root, &["test"]).unwrap();
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let commands = script
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with("cargo "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        [
+            "cargo fmt --all -- --check",
+            "cargo clippy --all --exclude generated --exclude vendored-crate -- -D warnings",
+            "cargo check --all --exclude generated --exclude vendored-crate",
+            "cargo test --all --exclude generated --exclude vendored-crate",
+        ]
+    );
 
-    let prepush_path = hook_path(&root, "pre-push");
+    // `--exclude` is omitted without `--all`
+    let root = cargo_project_for("exclude-packages-without-all");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-check\"]"
+    )
+    .unwrap();
+    let out = run_cargo_with_env(
+        &root,
+        ["test", "-vv"],
+        &[("CARGO_HUSKY_EXCLUDE", "generated")],
+    )
+    .unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains(
+            "'$CARGO_HUSKY_EXCLUDE' is ignored since `--exclude` requires run-for-all feature"
+        ),
+        "{}",
+        stderr
+    );
     let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo check")
+            .count(),
+        1
+    );
+}
 
-    // Replace version string in hook to older version
-    let before = format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION"));
-    let prev_version = decrease_patch(SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap());
-    let after = format!("set by cargo-husky v{}", prev_version);
-    let script = script.replacen(before.as_str(), after.as_str(), 1);
+#[test]
+fn profile_for_commands() {
+    let root = cargo_project_for("profile-for-commands");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-clippy\", \"run-cargo-check\", \"run-cargo-build\", \"run-cargo-test\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[
+            ("CARGO_HUSKY_PROFILE", "ci-profile"),
+            ("CARGO_HUSKY_PROFILE_EXCLUDE", "check"),
+            ("CARGO_HUSKY_BUILD_RELEASE", "true"),
+            ("CARGO_HUSKY_TEST_ARGS", "--locked"),
+        ],
+    )
+    .unwrap();
 
-    let modified_before = {
-        let mut f = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .truncate(true)
-            .open(&prepush_path)
-            .unwrap();
-        write!(f, "{}", script).unwrap();
-        f.metadata().unwrap().modified().unwrap()
-    };
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let commands = script
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with("cargo "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        [
+            "cargo clippy --all -- -D warnings",
+            "cargo check --all",
+            "cargo build --all --profile ci-profile",
+            "cargo test --all --profile ci-profile --locked",
+        ]
+    );
+}
 
-    // Remove 'target' directory to trigger compiling the package again.
-    // When package is updated, the package is re-compiled. But here, package itself is not updated.
-    // .git/hooks/pre-push was directly modified. So manually triggering re-compilation is necessary.
-    fs::remove_dir_all(root.join("target")).unwrap();
+#[test]
+fn extra_args_for_commands() {
+    let root = cargo_project_for("extra-args");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-test\", \"run-cargo-clippy\"]"
+    ).unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[
+            ("CARGO_HUSKY_TEST_ARGS", " --workspace  --locked "),
+            ("CARGO_HUSKY_CLIPPY_ARGS", "--all-targets --foo;bar"),
+        ],
+    )
+    .unwrap();
 
-    // Ensure modified time differs from previous
-    thread::sleep(time::Duration::from_secs(1));
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo test --all --workspace --locked")
+            .count(),
+        1
+    );
+    // Arguments are put before `--` and special characters are quoted
+    assert_eq!(
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo clippy --all --all-targets '--foo;bar' -- -D warnings")
+            .count(),
+        1
+    );
+}
 
-    run_cargo(&root, &["test"]).unwrap();
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn prerebase_hook() {
+    let root = cargo_project_for("prerebase-hook");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prerebase-hook\", \"run-cargo-check\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
-    let modified_after = File::open(&prepush_path)
-        .unwrap()
-        .metadata()
-        .unwrap()
-        .modified()
-        .unwrap();
-    // Modified time differs since the hook script was re-generated
-    assert_ne!(modified_before, modified_after);
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
 
-    // Check the version is updated in hook script
-    let script = get_hook_script(&root, "pre-push").unwrap();
+    let script = get_hook_script(&root, "pre-rebase").unwrap();
     assert!(script
         .lines()
         .nth(2)
         .unwrap()
         .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
-}
-
-macro_rules! another_hook_test {
-    ($testcase:ident, $content:expr) => {
-        #[test]
-        fn $testcase() {
-            let root = cargo_project_for(stringify!($testcase));
-            let prepush_path = hook_path(&root, "pre-push");
-            let content = $content.to_string();
-            let modified_before = {
-                let mut f = File::create(&prepush_path).unwrap();
-                writeln!(f, "{}", content).unwrap();
-                f.metadata().unwrap().modified().unwrap()
-            };
-
-            // Ensure modified time differs from previous if file were updated
-            thread::sleep(time::Duration::from_secs(1));
+    assert_eq!(script.lines().filter(|l| *l == "cargo check").count(), 1);
 
-            run_cargo(&root, &["test"]).unwrap();
+    // Arguments from Git (upstream and branch) do not break the hook
+    let status = Command::new("sh")
+        .arg(hook_path(&root, "pre-rebase"))
+        .args(["origin/main", "topic"])
+        .current_dir(&root)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
 
-            let modified_after = File::open(&prepush_path)
-                .unwrap()
-                .metadata()
-                .unwrap()
-                .modified()
-                .unwrap();
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn preautogc_hook() {
+    let root = cargo_project_for("preautogc-hook");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"preautogc-hook\", \"run-cargo-check\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
-            assert_eq!(modified_before, modified_after);
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
 
-            let script = get_hook_script(&root, "pre-push").unwrap();
-            assert_eq!(content + "\n", script);
-        }
-    };
-}
+    let script = get_hook_script(&root, "pre-auto-gc").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    assert_eq!(script.lines().filter(|l| *l == "cargo check").count(), 1);
 
-another_hook_test!(
-    another_hook_less_than_3_lines,
-    "#!/bin/sh\necho 'hook put by someone else'"
-);
-another_hook_test!(
-    another_hook_more_than_3_lines,
-    "#!/bin/sh\n\n\necho 'hook put by someone else'"
-);
+    let status = Command::new("sh")
+        .arg(hook_path(&root, "pre-auto-gc"))
+        .current_dir(&root)
+        .status()
+        .unwrap();
+    assert!(status.success());
 
-fn copy_dir_recursive(from: &Path, to: &Path) {
-    if !to.exists() {
-        fs::create_dir_all(to).unwrap();
-    }
-    for entry in fs::read_dir(from).unwrap() {
-        let entry = entry.unwrap();
-        let child_from = entry.path();
-        let child_to = to.join(child_from.strip_prefix(from).unwrap());
-        if entry.file_type().unwrap().is_dir() {
-            copy_dir_recursive(&child_from, &child_to);
-        } else {
-            fs::copy(child_from, child_to).unwrap();
-        }
-    }
+    // Failed command prevents garbage collection by exiting with non-zero status
+    fs::write(
+        root.join("src").join("lib.rs"),
+        "pub fn f() -> i32 { \"\" }\n",
+    )
+    .unwrap();
+    let status = Command::new("sh")
+        .arg(hook_path(&root, "pre-auto-gc"))
+        .current_dir(&root)
+        .status()
+        .unwrap();
+    assert!(!status.success());
 }
 
-fn setup_user_hooks_feature(root: &Path) {
+#[test]
+fn toolchain_for_commands() {
+    let root = cargo_project_for("toolchain");
     let mut cargo_toml = open_cargo_toml(&root);
     writeln!(
         cargo_toml,
-        "default-features = false\nfeatures = [\"user-hooks\"]" // pre-push will be ignored
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-test\", \"run-cargo-fmt\", \"run-cargo-clippy\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[
+            ("CARGO_HUSKY_TOOLCHAIN", "1.70.0"),
+            ("CARGO_HUSKY_FMT_TOOLCHAIN", "+nightly-2024-01-01"),
+            ("CARGO_HUSKY_CLIPPY_TOOLCHAIN", "nightly; rm -rf /"),
+        ],
     )
     .unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let commands = script
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with("cargo "))
+        .collect::<Vec<_>>();
+    // Invalid toolchain falls back to the toolchain for all commands
+    assert_eq!(
+        commands,
+        [
+            "cargo +nightly-2024-01-01 fmt -- --check",
+            "cargo clippy -- -D warnings",
+            "cargo +1.70.0 test",
+        ]
+    );
 }
 
 #[test]
-fn user_hooks() {
-    let root = cargo_project_for("user-hooks");
-    setup_user_hooks_feature(&root);
+fn run_cargo_nextest_instead_of_cargo_test() {
+    let root = cargo_project_for("features_nextest");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-test\", \"run-cargo-nextest\"]"
+    ).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "cargo nextest run --all")
+            .count(),
+        1
+    );
+    assert!(script.lines().all(|l| !l.starts_with("cargo test")));
+}
+
+#[test]
+fn run_cargo_test_doc_separately() {
+    let root = cargo_project_for("features_test_doc");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-test\", \"run-cargo-test-doc\"]"
+    ).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let commands = script
+        .lines()
+        .filter(|l| l.starts_with("cargo "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        [
+            "cargo test --all --lib --bins --tests",
+            "cargo test --all --doc"
+        ]
+    );
+
+    // Doc-tests are still run with nextest
+    let root = cargo_project_for("features_test_doc_nextest");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-nextest\", \"run-cargo-test-doc\"]"
+    ).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let commands = script
+        .lines()
+        .filter(|l| l.starts_with("cargo "))
+        .collect::<Vec<_>>();
+    assert_eq!(commands, ["cargo nextest run", "cargo test --doc"]);
+}
+
+#[test]
+fn offline_cargo_commands() {
+    let root = cargo_project_for("features_offline");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"offline\", \"run-cargo-fmt\", \"run-cargo-clippy\", \"run-cargo-check\", \"run-cargo-build\", \"run-cargo-doc\", \"run-cargo-test\", \"run-cargo-test-doc\", \"run-cargo-audit\"]"
+    ).unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_TEST_ARGS", "--locked")]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let commands = script
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with("cargo ") || l.starts_with("RUSTDOCFLAGS="))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        [
+            "cargo fmt --all -- --check",
+            "cargo clippy --all --offline -- -D warnings",
+            "cargo check --all --offline",
+            "cargo build --all --offline",
+            "RUSTDOCFLAGS=\"-D warnings\" cargo doc --no-deps --all --offline",
+            "cargo test --all --lib --bins --tests --offline --locked",
+            "cargo test --all --doc --offline",
+            // External subcommands are not affected
+            "cargo audit",
+        ]
+    );
+}
+
+#[test]
+fn custom_shell() {
+    for (idx, (shell, shebang)) in [("/bin/bash", "#!/bin/bash"), ("bash", "#!/bin/sh")]
+        .iter()
+        .enumerate()
+    {
+        let root = cargo_project_for(&format!("custom-shell-{}", idx));
+        run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_SHELL", shell)]).unwrap();
+
+        let script = get_hook_script(&root, "pre-push").unwrap();
+        assert_eq!(script.lines().next(), Some(*shebang));
+        assert!(script
+            .lines()
+            .nth(2)
+            .unwrap()
+            .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    }
+}
+
+// Run the generated hook with a stub `cargo` which records its arguments instead of running them.
+// Returns the recorded invocations in order
+#[cfg(not(target_os = "windows"))]
+fn run_hook_with_stub_cargo(root: &Path, hook: &str) -> Vec<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bin_dir = root.join("stub-bin");
+    fs::create_dir_all(&bin_dir).unwrap();
+    let log = root.join("stub-cargo.log");
+    let stub = bin_dir.join("cargo");
+    fs::write(
+        &stub,
+        format!("#!/bin/sh\necho \"$*\" >> '{}'\n", log.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!("{}:{}", bin_dir.display(), env::var("PATH").unwrap());
+    let out = Command::new("sh")
+        .arg(hook_path(root, hook))
+        .env("PATH", path)
+        .current_dir(root)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+
+    fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn run_generated_hook_with_stub_cargo() {
+    let root = cargo_project_for("stub-cargo");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-test\", \"run-cargo-check\", \"run-cargo-clippy\", \"run-cargo-fmt\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert_eq!(
+        run_hook_with_stub_cargo(&root, "pre-push"),
+        [
+            "fmt --version",
+            "fmt -- --check",
+            "clippy --version",
+            "clippy -- -D warnings",
+            "check",
+            "test",
+        ]
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn run_generated_hook_with_stub_cargo_for_all() {
+    let root = cargo_project_for("stub-cargo-for-all");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-test\", \"run-cargo-check\", \"run-cargo-clippy\", \"run-cargo-fmt\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert_eq!(
+        run_hook_with_stub_cargo(&root, "pre-push"),
+        [
+            "fmt --version",
+            "fmt --all -- --check",
+            "clippy --version",
+            "clippy --all -- -D warnings",
+            "check --all",
+            "test --all",
+        ]
+    );
+}
+
+#[test]
+fn order_of_commands() {
+    let root = cargo_project_for("order-of-commands");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-test\", \"run-cargo-check\", \"run-cargo-clippy\", \"run-cargo-fmt\"]"
+    )
+    .unwrap();
+    let commands = |script: String| {
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| l.starts_with("cargo "))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    // Cheap commands are run first by default
+    run_cargo(&root, ["test"]).unwrap();
+    assert_eq!(
+        commands(get_hook_script(&root, "pre-push").unwrap()),
+        [
+            "cargo fmt -- --check",
+            "cargo clippy -- -D warnings",
+            "cargo check",
+            "cargo test",
+        ]
+    );
+
+    fs::remove_file(hook_path(&root, "pre-push")).unwrap();
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let out = run_cargo_with_env(
+        &root,
+        ["test", "-vv"],
+        &[("CARGO_HUSKY_COMMAND_ORDER", "test, check,unknown")],
+    )
+    .unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("Unknown command \"unknown\" in '$CARGO_HUSKY_COMMAND_ORDER'"),
+        "Unexpected stderr: {}",
+        stderr
+    );
+    assert_eq!(
+        commands(get_hook_script(&root, "pre-push").unwrap()),
+        [
+            "cargo test",
+            "cargo check",
+            "cargo fmt -- --check",
+            "cargo clippy -- -D warnings",
+        ]
+    );
+}
+
+#[test]
+fn run_cargo_build() {
+    let root = cargo_project_for("features_build");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-check\", \"run-cargo-build\"]"
+    ).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script.lines().filter(|l| *l == "cargo check --all").count(),
+        1
+    );
+    assert_eq!(
+        script.lines().filter(|l| *l == "cargo build --all").count(),
+        1
+    );
+
+    fs::remove_file(hook_path(&root, "pre-push")).unwrap();
+    fs::remove_dir_all(root.join("target")).unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_BUILD_RELEASE", "1")]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "cargo build --all --release")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn run_cargo_doc() {
+    let root = cargo_project_for("features_doc");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-doc\"]"
+    ).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "RUSTDOCFLAGS=\"-D warnings\" cargo doc --no-deps --all")
+            .count(),
+        1
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn cargo_doc_fails_on_broken_link() {
+    let root = cargo_project_for("features_doc_broken_link");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-doc\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    writeln!(
+        File::create(root.join("src").join("lib.rs")).unwrap(),
+        "/// Link to [NotExist]\npub fn f() {{}}"
+    )
+    .unwrap();
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-push"))
+        .current_dir(&root)
+        .env_remove("RUSTDOCFLAGS")
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(stderr.contains("NotExist"), "Unexpected stderr: {}", stderr);
+}
+
+#[test]
+fn run_cargo_audit() {
+    let root = cargo_project_for("features_audit");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-audit\"]"
+    ).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "if command -v cargo-audit >/dev/null 2>&1; then")
+            .count(),
+        1
+    );
+    // --all is not added
+    assert_eq!(
+        script.lines().filter(|l| *l == "    cargo audit").count(),
+        1
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn skip_cargo_audit_when_not_installed() {
+    let root = cargo_project_for("features_audit_not_installed");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-audit\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-push"))
+        .env("PATH", "/usr/bin:/bin")
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+    assert!(
+        stdout.contains("cargo-audit is not installed"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn run_cargo_deny() {
+    let root = cargo_project_for("features_deny");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-cargo-deny\"]"
+    ).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "if command -v cargo-deny >/dev/null 2>&1; then")
+            .count(),
+        1
+    );
+    // --all is not added
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "    cargo deny check")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn run_sqlx_prepare_check() {
+    let root = cargo_project_for("features_sqlx");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-for-all\", \"run-sqlx-prepare-check\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "if command -v cargo-sqlx >/dev/null 2>&1; then")
+            .count(),
+        1
+    );
+    // --all is not added
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "    cargo sqlx prepare --check")
+            .count(),
+        1
+    );
+    assert!(
+        script.contains("Install it by `cargo install sqlx-cli`"),
+        "{}",
+        script
+    );
+}
+
+#[test]
+fn run_cargo_deny_with_subcommand() {
+    let root = cargo_project_for("features_deny_subcommand");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-deny\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_DENY_SUBCOMMAND", "check bans")],
+    )
+    .unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "    cargo deny check bans")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn run_cargo_test_with_filter_in_precommit() {
+    let root = cargo_project_for("features_test_filter");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(cargo_toml, "features = [\"precommit-hook\"]").unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[
+            ("CARGO_HUSKY_TEST_FILTER", "--lib fast_"),
+            ("CARGO_HUSKY_TEST_ARGS", "--locked"),
+        ],
+    )
+    .unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "cargo test --all --locked --lib fast_")
+            .count(),
+        1
+    );
+    // Full test suite is run in other hooks
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "cargo test --all --locked")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn run_cargo_clippy_with_flags() {
+    let root = cargo_project_for("features_clippy_flags");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-clippy\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[(
+            "CARGO_HUSKY_CLIPPY_FLAGS",
+            "-W clippy::pedantic -D warnings",
+        )],
+    )
+    .unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo clippy -- -W clippy::pedantic -D warnings")
+            .count(),
+        1
+    );
+
+    // Flags which would break the script fall back to the default
+    fs::remove_file(hook_path(&root, "pre-push")).unwrap();
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let out = run_cargo_with_env(
+        &root,
+        ["test", "-vv"],
+        &[("CARGO_HUSKY_CLIPPY_FLAGS", "-D warnings\nrm -rf /")],
+    )
+    .unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("'$CARGO_HUSKY_CLIPPY_FLAGS' must be non-empty flags in a single line"),
+        "{}",
+        stderr
+    );
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo clippy -- -D warnings")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn paths_in_comment_use_slash() {
+    let root = cargo_project_for("paths-in-comment");
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    // The manifest of the crate which generated the hook relative to the repository root
+    assert_eq!(
+        script.lines().nth(4),
+        Some("# Generated by building Cargo.toml")
+    );
+    assert!(!script.lines().nth(5).unwrap().contains('\\'));
+}
+
+#[test]
+fn hook_not_updated_twice() {
+    let root = cargo_project_for("not-update-twice");
+    run_cargo(&root, ["test"]).unwrap();
+
+    let prepush_path = hook_path(&root, "pre-push");
+
+    let first = File::open(&prepush_path)
+        .unwrap()
+        .metadata()
+        .unwrap()
+        .modified()
+        .unwrap();
+
+    // Remove 'target' directory to trigger compiling the package again.
+    // When package is updated, the package is re-compiled. But here, package itself is not updated.
+    // .git/hooks/pre-push was directly modified. So manually triggering re-compilation is necessary.
+    fs::remove_dir_all(root.join("target")).unwrap();
+
+    // Ensure modified time differs from previous
+    thread::sleep(time::Duration::from_secs(1));
+
+    let out = run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_VERBOSE", "1")]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    let expected = format!(
+        "already generated by cargo-husky v{}",
+        env!("CARGO_PKG_VERSION")
+    );
+    assert!(stderr.contains(&expected), "{}", stderr);
+    let second = File::open(&prepush_path)
+        .unwrap()
+        .metadata()
+        .unwrap()
+        .modified()
+        .unwrap();
+
+    assert_eq!(first, second); // Check the second `cargo test` does not modify hook script
+}
+
+#[test]
+fn regenerate_hook_script_on_package_update() {
+    let root = cargo_project_for("package-update");
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    let prepush_path = hook_path(&root, "pre-push");
+    let script = get_hook_script(&root, "pre-push").unwrap();
+
+    // Replace version string in hook to older version
+    let prev_version = decrease_patch(SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap());
+    let script = replace_hook_version(&script, &prev_version.to_string());
+
+    let modified_before = {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .read(true)
+            .truncate(true)
+            .open(&prepush_path)
+            .unwrap();
+        write!(f, "{}", script).unwrap();
+        f.metadata().unwrap().modified().unwrap()
+    };
+
+    // Remove 'target' directory to trigger compiling the package again.
+    // When package is updated, the package is re-compiled. But here, package itself is not updated.
+    // .git/hooks/pre-push was directly modified. So manually triggering re-compilation is necessary.
+    fs::remove_dir_all(root.join("target")).unwrap();
+
+    // Ensure modified time differs from previous
+    thread::sleep(time::Duration::from_secs(1));
+
+    let out = run_cargo(&root, ["test"]).unwrap();
+
+    // Upgrade is logged
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    let expected = format!(
+        "generated by older cargo-husky v{} to v{}",
+        prev_version,
+        env!("CARGO_PKG_VERSION")
+    );
+    assert!(stderr.contains(&expected), "{}", stderr);
+
+    let modified_after = File::open(&prepush_path)
+        .unwrap()
+        .metadata()
+        .unwrap()
+        .modified()
+        .unwrap();
+    // Modified time differs since the hook script was re-generated
+    assert_ne!(modified_before, modified_after);
+
+    // Check the version is updated in hook script
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+}
+
+#[test]
+fn backup_hook_before_regeneration() {
+    let prev_version = decrease_patch(SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap());
+    let (root, _) = install_over_hook_of_version("backup-hook", &prev_version.to_string());
+
+    let backup = get_hook_script(&root, "pre-push.bak").unwrap();
+    assert!(backup
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}:", prev_version).as_str()));
+}
+
+#[test]
+fn no_backup_of_foreign_hook() {
+    let root = cargo_project_for("no-backup-of-foreign-hook");
+    write!(
+        File::create(hook_path(&root, "pre-push")).unwrap(),
+        "#!/bin/sh\necho foreign\n"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(!hook_path(&root, "pre-push.bak").exists());
+}
+
+// Replace the version in the comment and the marker of the generated hook
+fn replace_hook_version(script: &str, version: &str) -> String {
+    let ours = env!("CARGO_PKG_VERSION");
+    script
+        .replacen(
+            &format!("set by cargo-husky v{}", ours),
+            &format!("set by cargo-husky v{}", version),
+            1,
+        )
+        .replacen(
+            &format!("cargo-husky-hook: v{}", ours),
+            &format!("cargo-husky-hook: v{}", version),
+            1,
+        )
+}
+
+fn install_over_hook_of_version(name: &str, version: &str) -> (PathBuf, String) {
+    let root = cargo_project_for(name);
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = replace_hook_version(&get_hook_script(&root, "pre-push").unwrap(), version);
+    write!(
+        File::create(hook_path(&root, "pre-push")).unwrap(),
+        "{}",
+        script
+    )
+    .unwrap();
+
+    // Trigger running the build script again
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    (root, stderr)
+}
+
+#[test]
+fn keep_hook_generated_by_newer_version() {
+    let mut newer = SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap();
+    newer.major += 1;
+    let newer = newer.to_string();
+    let (root, stderr) = install_over_hook_of_version("newer-version", &newer);
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", newer).as_str()));
+    assert!(
+        stderr.contains(&format!("was generated by newer cargo-husky v{}", newer)),
+        "Unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn update_hook_generated_by_prerelease_version() {
+    // Pre-release version is older than the release
+    let prerelease = format!("{}-beta.1", env!("CARGO_PKG_VERSION"));
+    let (root, stderr) = install_over_hook_of_version("prerelease-version", &prerelease);
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}:", env!("CARGO_PKG_VERSION")).as_str()));
+    assert!(
+        stderr.contains(&format!(
+            "generated by older cargo-husky v{} to v{}",
+            prerelease,
+            env!("CARGO_PKG_VERSION")
+        )),
+        "Unexpected stderr: {}",
+        stderr
+    );
+}
+
+macro_rules! another_hook_test {
+    ($testcase:ident, $content:expr) => {
+        #[test]
+        fn $testcase() {
+            let root = cargo_project_for(stringify!($testcase));
+            let prepush_path = hook_path(&root, "pre-push");
+            let content = $content.to_string();
+            let modified_before = {
+                let mut f = File::create(&prepush_path).unwrap();
+                writeln!(f, "{}", content).unwrap();
+                f.metadata().unwrap().modified().unwrap()
+            };
+
+            // Ensure modified time differs from previous if file were updated
+            thread::sleep(time::Duration::from_secs(1));
+
+            run_cargo(&root, ["test"]).unwrap();
+
+            let modified_after = File::open(&prepush_path)
+                .unwrap()
+                .metadata()
+                .unwrap()
+                .modified()
+                .unwrap();
+
+            assert_eq!(modified_before, modified_after);
+
+            let script = get_hook_script(&root, "pre-push").unwrap();
+            assert_eq!(content + "\n", script);
+        }
+    };
+}
+
+another_hook_test!(
+    another_hook_less_than_3_lines,
+    "#!/bin/sh\necho 'hook put by someone else'"
+);
+another_hook_test!(
+    another_hook_more_than_3_lines,
+    "#!/bin/sh\n\n\necho 'hook put by someone else'"
+);
+another_hook_test!(another_hook_1_line, "echo 'hook put by someone else'");
+
+#[test]
+fn regenerate_empty_hook() {
+    let root = cargo_project_for("regenerate-empty-hook");
+    let prepush_path = hook_path(&root, "pre-push");
+    File::create(&prepush_path).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+}
+
+#[test]
+fn detect_marker_not_at_third_line() {
+    let root = cargo_project_for("marker-not-at-third-line");
+    let prepush_path = hook_path(&root, "pre-push");
+    writeln!(
+        File::create(&prepush_path).unwrap(),
+        "#!/bin/sh\n# This hook was set by cargo-husky v0.0.1: {}",
+        env!("CARGO_PKG_HOMEPAGE")
+    )
+    .unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    // Short hook generated by older version is updated
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    assert_eq!(
+        script.lines().filter(|l| *l == "cargo test --all").count(),
+        1
+    );
+}
+
+#[test]
+fn detect_marker_in_edited_hook() {
+    let root = cargo_project_for("marker-in-edited-hook");
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let marker = format!("# cargo-husky-hook: v{}", env!("CARGO_PKG_VERSION"));
+    assert_eq!(script.lines().filter(|l| *l == marker).count(), 1);
+
+    // Lines are added before the marker and the legacy comment is removed
+    let edited = script
+        .replacen("#!/bin/sh\n", "#!/bin/sh\n# edited\n# by\n# user\n", 1)
+        .lines()
+        .filter(|l| !l.contains("This hook was set by cargo-husky"))
+        .map(|l| format!("{}\n", l))
+        .collect::<String>();
+    fs::write(hook_path(&root, "pre-push"), &edited).unwrap();
+
+    fs::remove_dir_all(root.join("target")).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    // Detected as the hook generated by the same version. It is not overwritten
+    assert_eq!(get_hook_script(&root, "pre-push").unwrap(), edited);
+    assert!(!hook_path(&root, "pre-push.bak").exists());
+}
+
+#[test]
+fn detect_marker_in_crlf_hook() {
+    let root = cargo_project_for("marker-in-crlf-hook");
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    let crlf = script.replace('\n', "\r\n");
+    fs::write(hook_path(&root, "pre-push"), &crlf).unwrap();
+
+    fs::remove_dir_all(root.join("target")).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    // Detected as the hook generated by the same version. It is not overwritten
+    assert_eq!(get_hook_script(&root, "pre-push").unwrap(), crlf);
+
+    // Detected as the hook generated by an older version. It is regenerated
+    let prev_version = decrease_patch(SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap());
+    let old = replace_hook_version(&crlf, &prev_version.to_string());
+    fs::write(hook_path(&root, "pre-push"), &old).unwrap();
+
+    fs::remove_dir_all(root.join("target")).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert_eq!(get_hook_script(&root, "pre-push").unwrap(), script);
+    assert_eq!(get_hook_script(&root, "pre-push.bak").unwrap(), old);
+}
+
+#[test]
+fn remove_generated_hook_when_feature_disabled() {
+    let root = cargo_project_for("remove-generated-hook");
+    let precommit_path = hook_path(&root, "pre-commit");
+    writeln!(
+        File::create(&precommit_path).unwrap(),
+        "#!/bin/sh\n#\n# This hook was set by cargo-husky v{}: {}\n\ncargo test",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_HOMEPAGE")
+    )
+    .unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(!precommit_path.exists());
+    assert!(hook_path(&root, "pre-push").is_file());
+}
+
+#[test]
+fn keep_hook_of_newer_version_when_feature_disabled() {
+    let root = cargo_project_for("keep-newer-hook");
+    let mut newer = SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap();
+    newer.major += 1;
+    let precommit_path = hook_path(&root, "pre-commit");
+    let content = format!(
+        "#!/bin/sh\n#\n# This hook was set by cargo-husky v{}: {}\n\ncargo test\n",
+        newer,
+        env!("CARGO_PKG_HOMEPAGE")
+    );
+    write!(File::create(&precommit_path).unwrap(), "{}", content).unwrap();
+
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+
+    assert_eq!(get_hook_script(&root, "pre-commit"), Some(content));
+    assert!(
+        stderr.contains(&format!(
+            "was generated by newer cargo-husky v{}. It is not removed",
+            newer
+        )),
+        "Unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn keep_foreign_hook_when_feature_disabled() {
+    let root = cargo_project_for("keep-foreign-hook");
+    let precommit_path = hook_path(&root, "pre-commit");
+    let content = "#!/bin/sh\n\n\necho 'hook put by someone else'\n";
+    write!(File::create(&precommit_path).unwrap(), "{}", content).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert_eq!(
+        get_hook_script(&root, "pre-commit"),
+        Some(content.to_string())
+    );
+}
+
+#[test]
+fn timeout_for_each_command() {
+    let root = cargo_project_for("timeout");
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_TIMEOUT", "30")]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "    cargo_husky_timeout='timeout 30'")
+            .count(),
+        1
+    );
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "$cargo_husky_timeout /bin/sh -c 'cargo test --all' \"$0\" \"$@\"")
+            .count(),
+        1
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn timeout_aborts_hook() {
+    // `timeout` may not be installed (e.g. macOS without coreutils). Then hooks run commands without
+    // time limit, so there is nothing to check
+    let has_timeout = Command::new("sh")
+        .args(["-c", "command -v timeout"])
+        .output()
+        .unwrap()
+        .status
+        .success();
+    if !has_timeout {
+        eprintln!("Skipped since timeout command is not installed");
+        return;
+    }
+
+    let root = cargo_project_for("timeout-aborts-hook");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nhooks.pre-commit = [\"sleep 10\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_TIMEOUT", "1")]).unwrap();
+
+    let start = time::Instant::now();
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(start.elapsed() < time::Duration::from_secs(10));
+}
+
+#[test]
+fn invalid_timeout_is_ignored() {
+    let root = cargo_project_for("invalid-timeout");
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_TIMEOUT", "1m")]).unwrap();
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script.lines().all(|l| !l.contains("cargo_husky_timeout")));
+    assert_eq!(
+        script.lines().filter(|l| *l == "cargo test --all").count(),
+        1
+    );
+}
+
+#[test]
+fn hooks_in_package_metadata() {
+    let root = cargo_project_for("package-metadata");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"
+[package.metadata.cargo-husky]
+hooks.pre-commit = [
+    "cargo fmt -- --check", # Check format first
+    'cargo clippy -- -D warnings',
+]
+hooks."post-merge" = ["echo \"merged\""]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    // Features are not used when hooks are configured in metadata
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    let commands = script
+        .lines()
+        .filter(|l| l.starts_with("cargo "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        ["cargo fmt -- --check", "cargo clippy -- -D warnings"]
+    );
+
+    let script = get_hook_script(&root, "post-merge").unwrap();
+    assert_eq!(
+        script.lines().filter(|l| *l == "echo \"merged\"").count(),
+        1
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn command_with_working_directory() {
+    let root = cargo_project_for("command-with-working-directory");
+    fs::create_dir_all(root.join("sub").join("dir")).unwrap();
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"
+[package.metadata.cargo-husky]
+hooks.pre-commit = [
+    {{ command = "pwd > ../../pwd.txt", dir = "sub/dir" }},
+    "pwd >> pwd.txt",
+]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "(cd 'sub/dir' && pwd > ../../pwd.txt)")
+            .count(),
+        1
+    );
+
+    let status = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // `cd` does not leak to the following command
+    let mut pwd = String::new();
+    File::open(root.join("pwd.txt"))
+        .unwrap()
+        .read_to_string(&mut pwd)
+        .unwrap();
+    let dirs = pwd.lines().map(PathBuf::from).collect::<Vec<_>>();
+    assert_eq!(dirs, [root.join("sub").join("dir"), root.clone()]);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn commands_for_each_branch() {
+    let root = cargo_project_for("commands-for-each-branch");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"
+[package.metadata.cargo-husky]
+hooks.pre-commit = ["echo default"]
+branches.main.pre-commit = ["echo main"]
+branches."release/*".pre-commit = ["echo release", "echo all"]
+branches.main.pre-push = ["echo push"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let run_hook = |hook| {
+        let out = Command::new("sh")
+            .arg(hook_path(&root, hook))
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .filter(|l| !l.starts_with('+'))
+            .map(|l| format!("{}\n", l))
+            .collect::<String>()
+    };
+
+    run_git(&root, ["symbolic-ref", "HEAD", "refs/heads/main"]);
+    assert_eq!(run_hook("pre-commit"), "main\n");
+    assert_eq!(run_hook("pre-push"), "push\n");
+
+    run_git(&root, ["symbolic-ref", "HEAD", "refs/heads/release/1.0"]);
+    assert_eq!(run_hook("pre-commit"), "release\nall\n");
+    // Hooks only configured for some branches run nothing on other branches
+    assert_eq!(run_hook("pre-push"), "");
+
+    run_git(&root, ["symbolic-ref", "HEAD", "refs/heads/feature"]);
+    assert_eq!(run_hook("pre-commit"), "default\n");
+
+    // Default commands are run on detached HEAD
+    set_git_config(&root, "user.name", "cargo-husky");
+    set_git_config(&root, "user.email", "cargo-husky@example.com");
+    run_git(
+        &root,
+        [
+            "commit",
+            "-q",
+            "--allow-empty",
+            "--no-verify",
+            "-m",
+            "initial",
+        ],
+    );
+    run_git(&root, ["checkout", "-q", "--detach"]);
+    assert_eq!(run_hook("pre-commit"), "default\n");
+}
+
+#[test]
+fn invalid_branch_pattern() {
+    let root = cargo_project_for("invalid-branch-pattern");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nbranches.\"main;rm\".pre-commit = [\"cargo test\"]"
+    )
+    .unwrap();
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(err.contains("Invalid branch pattern"), "{}", err);
+}
+
+#[test]
+fn invalid_hooks_in_package_metadata() {
+    let root = cargo_project_for("invalid-package-metadata");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nhooks.pre-commit = \"cargo test\""
+    )
+    .unwrap();
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(
+        err.contains("'hooks.pre-commit' must be an array of commands"),
+        "Unexpected output on `cargo test`: {}",
+        err
+    );
+}
+
+#[test]
+fn package_metadata_in_manifest_with_bom() {
+    let root = cargo_project_for("package-metadata-bom");
+    writeln!(
+        open_cargo_toml(&root),
+        "\n[package.metadata.cargo-husky]\nhooks.pre-commit = [\"cargo check\"]"
+    )
+    .unwrap();
+    // Cargo accepts Cargo.toml starting with UTF-8 BOM
+    let manifest = root.join("Cargo.toml");
+    let content = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, format!("\u{feff}{}", content)).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert_eq!(script.lines().filter(|l| *l == "cargo check").count(), 1);
+}
+
+#[test]
+fn hooks_in_config_file() {
+    let root = cargo_project_for("config-file");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nhooks.post-merge = [\"cargo test\"]"
+    )
+    .unwrap();
+    fs::create_dir(root.join(".cargo-husky")).unwrap();
+    writeln!(
+        File::create(root.join(".cargo-husky").join("config.toml")).unwrap(),
+        r#"[hooks]
+pre-commit = ["cargo clippy -- -D warnings"]
+pre-push = ["cargo test"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    // Metadata in Cargo.toml is not used when the configuration file exists
+    assert_eq!(get_hook_script(&root, "post-merge"), None);
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "cargo clippy -- -D warnings")
+            .count(),
+        1
+    );
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(script.lines().filter(|l| *l == "cargo test").count(), 1);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn prepush_hook_exposes_remote_and_refs() {
+    let root = cargo_project_for("prepush-remote");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"
+[package.metadata.cargo-husky]
+hooks.pre-push = ['echo "remote=$cargo_husky_remote url=$cargo_husky_remote_url"', 'printf "%s\n" "$cargo_husky_refs" >&2']"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let refs = "refs/heads/main 1111 refs/heads/main 0000\nrefs/tags/v1 2222 refs/tags/v1 0000";
+    let mut child = Command::new("sh")
+        .arg(hook_path(&root, "pre-push"))
+        .arg("origin")
+        .arg("https://example.com/repo.git")
+        .current_dir(&root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    writeln!(child.stdin.take().unwrap(), "{}", refs).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "{:?}", out);
+
+    let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l == "remote=origin url=https://example.com/repo.git"),
+        "{}",
+        stdout
+    );
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert_eq!(stderr.trim_end(), refs);
+}
+
+#[test]
+fn skip_in_metadata() {
+    let root = cargo_project_for("skip-in-metadata");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(cargo_toml, "\n[package.metadata.cargo-husky]\nskip = true").unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+
+    let root = cargo_project_for("skip-in-metadata-false");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(cargo_toml, "\n[package.metadata.cargo-husky]\nskip = false").unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+    assert!(get_hook_script(&root, "pre-push").is_some());
+
+    let root = cargo_project_for("skip-in-metadata-invalid");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nskip = \"yes\""
+    )
+    .unwrap();
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(
+        err.contains("'skip' must be a boolean"),
+        "Unexpected output on `cargo test`: {}",
+        err
+    );
+}
+
+#[test]
+fn invalid_config_file() {
+    let root = cargo_project_for("invalid-config-file");
+    fs::create_dir(root.join(".cargo-husky")).unwrap();
+    writeln!(
+        File::create(root.join(".cargo-husky").join("config.toml")).unwrap(),
+        "hooks.pre-commit = [\"cargo test\""
+    )
+    .unwrap();
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(
+        err.contains("config.toml"),
+        "Unexpected output on `cargo test`: {}",
+        err
+    );
+    assert_eq!(get_hook_script(&root, "pre-commit"), None);
+}
+
+#[test]
+fn assign_commands_to_hooks() {
+    let root = cargo_project_for("assign-commands");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"default-features = false
+features = ["prepush-hook", "precommit-hook", "postmerge-hook", "run-cargo-test", "run-cargo-fmt"]
+
+[package.metadata.cargo-husky]
+precommit-commands = ["fmt"]
+prepush-commands = ["test", "clippy"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let commands = |hook| {
+        get_hook_script(&root, hook)
+            .unwrap()
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| l.starts_with("cargo "))
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(commands("pre-commit"), ["cargo fmt -- --check"]);
+    // Assigned commands are run even if their features are not enabled
+    assert_eq!(
+        commands("pre-push"),
+        ["cargo test", "cargo clippy -- -D warnings"]
+    );
+    // Hooks without assignment run all enabled commands
+    assert_eq!(
+        commands("post-merge"),
+        ["cargo fmt -- --check", "cargo test"]
+    );
+}
+
+#[test]
+fn unknown_command_assigned_to_hook() {
+    let root = cargo_project_for("unknown-assigned-command");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nprepush-commands = [\"tset\"]"
+    )
+    .unwrap();
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(
+        err.contains("Unknown command \"tset\" in 'prepush-commands'"),
+        "Unexpected output on `cargo test`: {}",
+        err
+    );
+}
+
+fn set_git_config(root: &Path, key: &str, value: &str) {
+    let status = Command::new("git")
+        .args(["config", key, value])
+        .current_dir(root)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn run_git<'a>(root: &Path, args: impl IntoIterator<Item = &'a str>) {
+    let out = Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+}
+
+#[test]
+fn install_other_hooks_when_one_failed() {
+    let root = cargo_project_for("install-other-hooks");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"precommit-hook\", \"postmerge-hook\"]"
+    )
+    .unwrap();
+    // Hook file cannot be written since a directory exists at the path
+    fs::create_dir_all(hook_path(&root, "pre-push").join("dir")).unwrap();
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(err.contains("Failed to install 1 hook(s):"), "{}", err);
+    assert!(err.contains("pre-push: IO error:"), "{}", err);
+    // Other hooks were still installed
+    assert!(err.contains("installed: pre-commit, post-merge"), "{}", err);
+    assert!(get_hook_script(&root, "pre-commit").is_some());
+    assert!(get_hook_script(&root, "post-merge").is_some());
+}
+
+#[test]
+fn frozen_hooks_are_not_regenerated() {
+    let root = cargo_project_for("freeze-hooks");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"precommit-hook\", \"run-cargo-test\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    // Customize the generated hook by hand and pretend cargo-husky was updated
+    let prev_version = decrease_patch(SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap());
+    for hook in ["pre-commit", "pre-push"] {
+        let script = get_hook_script(&root, hook).unwrap();
+        let script = replace_hook_version(&script, &prev_version.to_string());
+        fs::write(hook_path(&root, hook), script + "\necho customized\n").unwrap();
+    }
+    let customized = get_hook_script(&root, "pre-commit").unwrap();
+
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let out = run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_FREEZE", "pre-commit, post-merge")],
+    )
+    .unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("skipped-frozen: pre-commit\n"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("overwritten: pre-push\n"), "{}", stderr);
+    assert_eq!(get_hook_script(&root, "pre-commit").unwrap(), customized);
+    assert!(!get_hook_script(&root, "pre-push")
+        .unwrap()
+        .contains("customized"));
+
+    // Frozen hook is not removed even when its feature is disabled
+    let mut cargo_toml = fs::read_to_string(root.join("Cargo.toml")).unwrap();
+    cargo_toml = cargo_toml.replace("\"precommit-hook\", ", "");
+    fs::write(root.join("Cargo.toml"), cargo_toml).unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_FREEZE", "pre-commit")]).unwrap();
+    assert_eq!(get_hook_script(&root, "pre-commit").unwrap(), customized);
+}
+
+#[test]
+fn skip_installing_hooks_in_ci() {
+    let root = cargo_project_for("skip-in-ci");
+    let out = run_cargo_with_env(&root, ["test"], &[("GITHUB_ACTIONS", "true")]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("Skipped installing hooks in CI detected by $GITHUB_ACTIONS"),
+        "{}",
+        stderr
+    );
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+
+    // CI=false does not mean CI
+    let root = cargo_project_for("skip-in-ci-false");
+    run_cargo_with_env(&root, ["test"], &[("CI", "false")]).unwrap();
+    assert!(get_hook_script(&root, "pre-push").is_some());
+
+    let root = cargo_project_for("install-in-ci");
+    writeln!(open_cargo_toml(&root), "features = [\"install-in-ci\"]").unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CI", "true")]).unwrap();
+    assert!(get_hook_script(&root, "pre-push").is_some());
+}
+
+#[test]
+fn warn_when_out_dir_is_not_set() {
+    let root = cargo_project_for("out-dir-not-set");
+    run_cargo(&root, ["test"]).unwrap();
+    fs::remove_file(hook_path(&root, "pre-push")).unwrap();
+
+    // Run the build script directly as if it were invoked outside cargo
+    let build_dir = root.join("target").join("debug").join("build");
+    let script = fs::read_dir(build_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|p| {
+            p.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("cargo-husky-")
+        })
+        .flat_map(|p| fs::read_dir(p).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .find(|p| {
+            p.file_stem()
+                .map(|s| s == "build-script-build")
+                .unwrap_or(false)
+        })
+        .unwrap();
+    let mut cmd = Command::new(script);
+    for var in CI_ENV_VARS {
+        cmd.env_remove(var);
+    }
+    let out = cmd
+        .current_dir(&root)
+        .env_remove("OUT_DIR")
+        .env_remove("GIT_DIR")
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("$OUT_DIR is not set"), "{}", stderr);
+    assert!(!hook_path(&root, "pre-push").exists());
+}
+
+#[test]
+fn install_hooks_to_bare_repository() {
+    let root = cargo_project_for("bare-repository");
+    fs::remove_dir_all(root.join(".git")).unwrap();
+    fs::create_dir_all(root.join("hooks")).unwrap();
+    fs::create_dir_all(root.join("objects")).unwrap();
+    fs::create_dir_all(root.join("refs")).unwrap();
+    writeln!(
+        File::create(root.join("HEAD")).unwrap(),
+        "ref: refs/heads/main"
+    )
+    .unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(root.join("hooks").join("pre-push").is_file());
+}
+
+#[test]
+fn install_hooks_to_core_hooks_path() {
+    let root = cargo_project_for("core-hooks-path");
+    set_git_config(&root, "core.hooksPath", ".githooks");
+    fs::create_dir_all(root.join(".githooks")).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(root.join(".githooks").join("pre-push").is_file());
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+}
+
+#[test]
+fn install_hooks_to_githooks_dir() {
+    let root = cargo_project_for("githooks-dir");
+    writeln!(open_cargo_toml(&root), "features = [\"githooks-dir\"]").unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(root.join(".githooks").join("pre-push").is_file());
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+    let out = Command::new("git")
+        .args(["config", "core.hooksPath"])
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert_eq!(
+        str::from_utf8(out.stdout.as_slice()).unwrap(),
+        ".githooks\n"
+    );
+
+    // core.hooksPath set to another directory is not overwritten
+    let root = cargo_project_for("githooks-dir-already-set");
+    writeln!(open_cargo_toml(&root), "features = [\"githooks-dir\"]").unwrap();
+    set_git_config(&root, "core.hooksPath", "my-hooks");
+    fs::create_dir_all(root.join("my-hooks")).unwrap();
+
+    let out = run_cargo(&root, ["test", "-vv"]).unwrap();
+
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("'core.hooksPath' is already set to"),
+        "{}",
+        stderr
+    );
+    assert!(root.join("my-hooks").join("pre-push").is_file());
+    assert!(!root.join(".githooks").exists());
+}
+
+#[test]
+fn create_core_hooks_path_when_not_found() {
+    let root = cargo_project_for("core-hooks-path-not-found");
+    set_git_config(&root, "core.hooksPath", "my-hooks/nested");
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(root
+        .join("my-hooks")
+        .join("nested")
+        .join("pre-push")
+        .is_file());
+}
+
+#[test]
+fn create_hooks_dir_when_not_found() {
+    let root = cargo_project_for("hooks-dir-not-found");
+    fs::remove_dir_all(root.join(".git").join("hooks")).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(get_hook_script(&root, "pre-push").is_some());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn hooks_dir_not_writable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Permission is not checked for root user
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+
+    let root = cargo_project_for("hooks-dir-not-writable");
+    let hooks_dir = root.join(".git").join("hooks");
+    fs::set_permissions(&hooks_dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+    let out = run_cargo(&root, ["test", "-vv"]);
+    fs::set_permissions(&hooks_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let out = out.unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("is not writable. No hook was installed"),
+        "Unexpected stderr: {}",
+        stderr
+    );
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+}
+
+#[test]
+fn chain_existing_hook() {
+    let root = cargo_project_for("chain-existing");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(cargo_toml, "features = [\"chain-existing\"]").unwrap();
+
+    let prepush_path = hook_path(&root, "pre-push");
+    let local_path = hook_path(&root, "pre-push.local");
+    let content = "#!/bin/sh\necho 'hook put by someone else'\n";
+    write!(File::create(&prepush_path).unwrap(), "{}", content).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert_eq!(
+        get_hook_script(&root, "pre-push.local"),
+        Some(content.to_string())
+    );
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| {
+                *l == "printf '%s\\n' \"$cargo_husky_refs\" | \"$(dirname \"$0\")/pre-push.local\" \"$@\""
+            })
+            .count(),
+        1
+    );
+    assert_eq!(
+        script.lines().filter(|l| *l == "cargo test --all").count(),
+        1
+    );
+
+    // Chained hook is not chained again
+    fs::remove_dir_all(root.join("target")).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert_eq!(get_hook_script(&root, "pre-push").unwrap(), script);
+    assert_eq!(
+        get_hook_script(&root, "pre-push.local"),
+        Some(content.to_string())
+    );
+    assert!(!hook_path(&root, "pre-push.local.local").exists());
+    assert!(local_path.is_file());
+}
+
+#[test]
+fn force_overwrite_foreign_hook() {
+    let root = cargo_project_for("force-overwrite");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(cargo_toml, "features = [\"force-overwrite\"]").unwrap();
+
+    let content = "#!/bin/sh\necho 'hook put by someone else'\n";
+    write!(
+        File::create(hook_path(&root, "pre-push")).unwrap(),
+        "{}",
+        content
+    )
+    .unwrap();
+
+    let out = run_cargo(&root, ["test", "-vv"]).unwrap();
+
+    assert_eq!(
+        get_hook_script(&root, "pre-push.orig"),
+        Some(content.to_string())
+    );
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script.contains("cargo test --all"), "{}", script);
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("put by someone else since force-overwrite feature is enabled"),
+        "{}",
+        stderr
+    );
+
+    // Our hook is not moved aside
+    fs::remove_dir_all(root.join("target")).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+    assert_eq!(get_hook_script(&root, "pre-push").unwrap(), script);
+    assert_eq!(
+        get_hook_script(&root, "pre-push.orig"),
+        Some(content.to_string())
+    );
+}
+
+#[test]
+fn report_disposition_of_each_hook() {
+    let root = cargo_project_for("report-dispositions");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "features = [\"precommit-hook\", \"postmerge-hook\"]"
+    )
+    .unwrap();
+    write!(
+        File::create(hook_path(&root, "post-merge")).unwrap(),
+        "#!/bin/sh\necho 'hook put by someone else'\n"
+    )
+    .unwrap();
+
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("installed: pre-push, pre-commit\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("skipped-foreign: post-merge\n"),
+        "{}",
+        stderr
+    );
+
+    let prev_version = decrease_patch(SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap());
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    fs::write(
+        hook_path(&root, "pre-commit"),
+        replace_hook_version(&script, &prev_version.to_string()),
+    )
+    .unwrap();
+
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(stderr.contains("overwritten: pre-commit\n"), "{}", stderr);
+    assert!(
+        stderr.contains("skipped-same-version: pre-push\n"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("installed:"), "{}", stderr);
+
+    // No disposition is reported when no hook is enabled. Instead, available features are shown
+    let root = cargo_project_for("report-no-hook");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"run-cargo-test\"]"
+    )
+    .unwrap();
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(!stderr.contains("installed:"), "{}", stderr);
+    assert!(
+        stderr.contains("No hook was installed since no hook feature is enabled. Enable one of features prepush-hook, precommit-hook, postmerge-hook, commit-msg-hook, "),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("preautogc-hook"), "{}", stderr);
+    assert!(stderr.contains("user-hooks"), "{}", stderr);
+}
+
+fn copy_dir_recursive(from: &Path, to: &Path) {
+    if !to.exists() {
+        fs::create_dir_all(to).unwrap();
+    }
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let child_from = entry.path();
+        let child_to = to.join(child_from.strip_prefix(from).unwrap());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir_recursive(&child_from, &child_to);
+        } else {
+            fs::copy(child_from, child_to).unwrap();
+        }
+    }
+}
+
+fn setup_user_hooks_feature(root: &Path) {
+    let mut cargo_toml = open_cargo_toml(root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"user-hooks\"]" // pre-push will be ignored
+    )
+    .unwrap();
+}
+
+#[test]
+fn user_hooks() {
+    let root = cargo_project_for("user-hooks");
+    setup_user_hooks_feature(&root);
+
+    let user_hooks = TESTDIR.join("user-hooks");
+    copy_dir_recursive(&user_hooks.join(".cargo-husky"), &root.join(".cargo-husky"));
+
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(!stderr.contains("no hook feature is enabled"), "{}", stderr);
+
+    assert!(!hook_path(&root, "pre-push").exists()); // Default features are ignored
+    assert!(hook_path(&root, "pre-commit").is_file());
+    assert!(hook_path(&root, "post-merge").is_file());
+
+    let check_line = format!(
+        "# This hook was set by cargo-husky v{}: {}",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_HOMEPAGE")
+    );
+
+    let marker = format!("# cargo-husky-hook: v{}", env!("CARGO_PKG_VERSION"));
+
+    let s = get_hook_script(&root, "pre-commit").unwrap();
+    assert_eq!(s.lines().next(), Some("#! /bin/sh"));
+    assert_eq!(s.lines().nth(2), Some(check_line.as_str()));
+    assert_eq!(s.lines().nth(3), Some(marker.as_str()));
+    assert_eq!(
+        s.lines().nth(5),
+        Some("# This is a user script for pre-commit hook with shebang")
+    );
+
+    let s = get_hook_script(&root, "post-merge").unwrap();
+    assert_eq!(s.lines().next(), Some("#"));
+    assert_eq!(s.lines().nth(2), Some(check_line.as_str()));
+    assert_eq!(s.lines().nth(3), Some(marker.as_str()));
+    assert_eq!(
+        s.lines().nth(4),
+        Some("# Script without shebang (I'm not sure this is useful)")
+    );
+}
+
+#[test]
+fn user_hooks_with_invalid_config_file() {
+    let root = cargo_project_for("user-hooks-invalid-config");
+    setup_user_hooks_feature(&root);
+
+    let user_hooks = TESTDIR.join("user-hooks");
+    copy_dir_recursive(&user_hooks.join(".cargo-husky"), &root.join(".cargo-husky"));
+    writeln!(
+        File::create(root.join(".cargo-husky").join("config.toml")).unwrap(),
+        "hooks.pre-commit = [\"cargo test\""
+    )
+    .unwrap();
+
+    // Configuration is not used by user hooks so they are installed with a warning
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("Ignored configuration since it cannot be read"),
+        "{}",
+        stderr
+    );
+    assert!(hook_path(&root, "pre-commit").is_file());
+    assert!(hook_path(&root, "post-merge").is_file());
+}
+
+fn assert_user_hooks_error(root: &Path) {
+    match run_cargo(root, ["test"]) {
+        Ok(out) => panic!("`cargo test` has unexpectedly successfully done: {:?}", out),
+        Err(err) => assert!(
+            err.contains("User hooks directory is not found or no executable file is found in"),
+            "Unexpected output on `cargo test`: {}",
+            err
+        ),
+    }
+}
+
+#[test]
+fn user_hooks_dir_not_found() {
+    let root = cargo_project_for("user-hooks-dir-not-found");
+    setup_user_hooks_feature(&root);
+    assert_user_hooks_error(&root);
+}
+
+#[test]
+fn user_hooks_dir_is_empty() {
+    for (idx, dir_path) in [
+        PathBuf::from(".cargo-husky"),
+        Path::new(".cargo-husky").join("hooks"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let root = cargo_project_for(&format!("user-hooks-dir-empty-{}", idx));
+        setup_user_hooks_feature(&root);
+
+        fs::create_dir_all(root.join(dir_path)).unwrap();
+
+        assert_user_hooks_error(&root);
+    }
+}
+
+#[test]
+#[cfg(target_os = "windows")]
+fn user_hooks_skip_non_script_files_on_windows() {
+    let root = cargo_project_for("user-hooks-non-script-files");
+    setup_user_hooks_feature(&root);
+
+    let dir = root.join(".cargo-husky").join("hooks");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pre-commit"), "#!/bin/sh\necho 'pre-commit'\n").unwrap();
+    fs::write(dir.join("check.sh"), "#!/bin/sh\necho 'check'\n").unwrap();
+    fs::write(dir.join("README.md"), "# Hooks\n\nThis is not a hook\n").unwrap();
+    fs::write(dir.join("pre-commit~"), "#!/bin/sh\necho 'backup'\n").unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(hook_path(&root, "pre-commit").is_file());
+    assert!(hook_path(&root, "check.sh").is_file());
+    assert!(!hook_path(&root, "README.md").exists());
+    assert!(!hook_path(&root, "pre-commit~").exists());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn user_hooks_dir_only_contains_non_executable_file() {
+    let root = cargo_project_for("user-hooks-dir-without-executables");
+    setup_user_hooks_feature(&root);
+
+    let mut p = root.join(".cargo-husky");
+    p.push("hooks");
+    fs::create_dir_all(&p).unwrap();
+    let f1 = p.join("non-executable-file1");
+    writeln!(File::create(&f1).unwrap(), "this\nis\nnormal\ntest\nfile").unwrap();
+    assert!(f1.exists());
+    let f2 = p.join("non-executable-file2");
+    writeln!(
+        File::create(&f2).unwrap(),
+        "this\nis\nalso\nnormal\ntest\nfile"
+    )
+    .unwrap();
+    assert!(f2.exists());
+
+    assert_user_hooks_error(&root);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn copied_user_hooks_are_executable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("copied-user-hooks-are-executable");
+    setup_user_hooks_feature(&root);
+
+    let mut p = root.join(".cargo-husky");
+
+    let user_hooks = TESTDIR.join("user-hooks");
+    copy_dir_recursive(&user_hooks.join(".cargo-husky"), &p);
+
+    p.push("hooks");
+    p.push("non-executable-file.txt");
+    writeln!(File::create(p).unwrap(), "foo\nbar\npiyo").unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    for name in &["pre-commit", "post-merge"] {
+        let hook = File::open(hook_path(&root, name)).unwrap();
+        let mode = hook.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o555, 0o555);
+    }
+
+    assert!(!hook_path(&root, "non-executable-file.txt").exists());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn user_hooks_with_helpers_in_subdirectories() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("user-hooks-with-helpers");
+    setup_user_hooks_feature(&root);
+
+    let user_hooks = TESTDIR.join("user-hooks");
+    copy_dir_recursive(&user_hooks.join(".cargo-husky"), &root.join(".cargo-husky"));
+
+    let lib = root.join(".cargo-husky").join("hooks").join("lib");
+    fs::create_dir_all(lib.join("bin")).unwrap();
+    writeln!(File::create(lib.join("common.sh")).unwrap(), "FOO=1").unwrap();
+    let helper = lib.join("bin").join("helper");
+    writeln!(
+        File::create(&helper).unwrap(),
+        "#!/bin/sh
+echo helper"
+    )
+    .unwrap();
+    fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(hook_path(&root, "pre-commit").is_file());
+
+    let mode = |p: PathBuf| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+    let lib = hook_path(&root, "lib");
+    let mut src = String::new();
+    File::open(lib.join("common.sh"))
+        .unwrap()
+        .read_to_string(&mut src)
+        .unwrap();
+    // Helpers are copied as-is without cargo-husky header
+    assert_eq!(src, "FOO=1\n");
+    assert_eq!(mode(lib.join("common.sh")) & 0o111, 0);
+    assert_eq!(mode(lib.join("bin").join("helper")), 0o755);
+}
+
+#[test]
+fn install_user_hooks_when_copying_helpers_failed() {
+    let root = cargo_project_for("user-hooks-helpers-failed");
+    setup_user_hooks_feature(&root);
 
     let user_hooks = TESTDIR.join("user-hooks");
     copy_dir_recursive(&user_hooks.join(".cargo-husky"), &root.join(".cargo-husky"));
+    let lib = root.join(".cargo-husky").join("hooks").join("lib");
+    fs::create_dir_all(&lib).unwrap();
+    writeln!(File::create(lib.join("common.sh")).unwrap(), "FOO=1").unwrap();
+    // Helpers cannot be copied since a file exists at the path of the directory
+    fs::write(hook_path(&root, "lib"), "").unwrap();
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(err.contains("Failed to install 1 hook(s):"), "{}", err);
+    assert!(err.contains("lib/: IO error:"), "{}", err);
+    // Hooks were still installed
+    assert!(hook_path(&root, "pre-commit").is_file());
+    assert!(hook_path(&root, "post-merge").is_file());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn user_hook_with_directive() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("user-hook-directive");
+    setup_user_hooks_feature(&root);
+
+    let dir = root.join(".cargo-husky").join("hooks");
+    fs::create_dir_all(&dir).unwrap();
+    let src = dir.join("run-tests.sh");
+    write!(
+        File::create(&src).unwrap(),
+        "#!/bin/sh\n# cargo-husky: hook=pre-push\necho 'running tests'\n"
+    )
+    .unwrap();
+    fs::set_permissions(&src, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let out = run_cargo(&root, ["test"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(stderr.contains("installed: pre-push\n"), "{}", stderr);
+
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script.contains("echo 'running tests'"), "{}", script);
+    assert!(!hook_path(&root, "run-tests.sh").exists());
+
+    // Unknown hook name is an error
+    let root = cargo_project_for("user-hook-invalid-directive");
+    setup_user_hooks_feature(&root);
+
+    let dir = root.join(".cargo-husky").join("hooks");
+    fs::create_dir_all(&dir).unwrap();
+    let src = dir.join("run-tests.sh");
+    write!(
+        File::create(&src).unwrap(),
+        "#!/bin/sh\n# cargo-husky: hook=pre-pushh\necho 'running tests'\n"
+    )
+    .unwrap();
+    fs::set_permissions(&src, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(
+        err.contains("Invalid directive \"hook=pre-pushh\" in user hook"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn user_hook_permissions_are_preserved() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("user-hook-permissions");
+    setup_user_hooks_feature(&root);
+
+    let dir = root.join(".cargo-husky").join("hooks");
+    fs::create_dir_all(&dir).unwrap();
+    for (name, mode) in &[
+        ("pre-commit", 0o775),
+        ("pre-push", 0o555),
+        ("post-commit", 0o4755),
+    ] {
+        let src = dir.join(name);
+        writeln!(File::create(&src).unwrap(), "#!/bin/sh\necho {}", name).unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(*mode)).unwrap();
+    }
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    let mode = |name| {
+        fs::metadata(hook_path(&root, name))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777
+    };
+    assert_eq!(mode("pre-commit"), 0o775);
+    assert_eq!(mode("pre-push"), 0o555);
+    // setuid bit is not copied
+    assert_eq!(mode("post-commit"), 0o755);
+
+    // Read-only hook is still regenerated on update
+    let prev_version = decrease_patch(SemVer::parse(env!("CARGO_PKG_VERSION")).unwrap());
+    let prepush = hook_path(&root, "pre-push");
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    fs::set_permissions(&prepush, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(
+        &prepush,
+        replace_hook_version(&script, &prev_version.to_string()),
+    )
+    .unwrap();
+    fs::set_permissions(&prepush, fs::Permissions::from_mode(0o555)).unwrap();
+    fs::remove_dir_all(root.join("target")).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert_eq!(get_hook_script(&root, "pre-push").unwrap(), script);
+    assert_eq!(mode("pre-push"), 0o555);
+}
+
+#[test]
+fn user_hook_content_is_preserved() {
+    let root = cargo_project_for("user-hook-content-preserved");
+    setup_user_hooks_feature(&root);
+
+    let dir = root.join(".cargo-husky").join("hooks");
+    fs::create_dir_all(&dir).unwrap();
+    let content = b"#!/bin/sh\r\ncat <<EOS\r\nfoo\r\nEOS\r\n\xff\xfe no trailing newline";
+    let src = dir.join("pre-commit");
+    File::create(&src).unwrap().write_all(content).unwrap();
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    let mut installed = vec![];
+    File::open(hook_path(&root, "pre-commit"))
+        .unwrap()
+        .read_to_end(&mut installed)
+        .unwrap();
+    let header = format!(
+        "#!/bin/sh\r\n#\r\n# This hook was set by cargo-husky v{}: {}\r\n# cargo-husky-hook: v{}\r\n",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_HOMEPAGE"),
+        env!("CARGO_PKG_VERSION"),
+    );
+    assert_eq!(&installed[..header.len()], header.as_bytes());
+    assert_eq!(
+        &installed[header.len()..],
+        &content[b"#!/bin/sh\r\n".len()..]
+    );
+}
+
+#[test]
+fn user_hooks_in_custom_dir() {
+    let root = cargo_project_for("user-hooks-custom-dir");
+    setup_user_hooks_feature(&root);
+
+    let user_hooks = TESTDIR.join("user-hooks");
+    copy_dir_recursive(
+        &user_hooks.join(".cargo-husky").join("hooks"),
+        &root.join("tools").join("githooks"),
+    );
+
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_HOOKS_DIR", "tools/githooks")],
+    )
+    .unwrap();
+
+    assert!(hook_path(&root, "pre-commit").is_file());
+    assert!(hook_path(&root, "post-merge").is_file());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn user_hooks_in_husky_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("user-hooks-husky-dir");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"user-hooks\", \"husky-hooks\"]"
+    )
+    .unwrap();
+
+    // Layout of husky v5+. Hook scripts are not necessarily executable
+    let husky_dir = root.join(".husky");
+    fs::create_dir_all(husky_dir.join("_")).unwrap();
+    fs::write(husky_dir.join("pre-commit"), "echo 'from husky'\n").unwrap();
+    fs::write(husky_dir.join("post-merge"), "echo 'from husky'\n").unwrap();
+    fs::write(husky_dir.join("_").join("husky.sh"), "echo 'helper'\n").unwrap();
+    fs::write(husky_dir.join("README.md"), "not a hook\n").unwrap();
+
+    // The hook in .cargo-husky/hooks takes precedence over the same hook in .husky
+    let hooks_dir = root.join(".cargo-husky").join("hooks");
+    fs::create_dir_all(&hooks_dir).unwrap();
+    let precommit = hooks_dir.join("pre-commit");
+    fs::write(&precommit, "#!/bin/sh\necho 'from cargo-husky'\n").unwrap();
+    fs::set_permissions(&precommit, fs::Permissions::from_mode(0o755)).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(script.contains("from cargo-husky"), "{}", script);
+    let script = get_hook_script(&root, "post-merge").unwrap();
+    assert!(script.contains("set by cargo-husky"), "{}", script);
+    assert!(script.ends_with("echo 'from husky'\n"), "{}", script);
+    assert!(hook_path(&root, "_").join("husky.sh").is_file());
+    assert!(!hook_path(&root, "README.md").exists());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn husky_dir_ignored_without_feature() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("user-hooks-husky-dir-disabled");
+    setup_user_hooks_feature(&root);
+
+    // .husky left by husky of JavaScript is not installed unless husky-hooks feature is enabled
+    let husky_dir = root.join(".husky");
+    fs::create_dir_all(&husky_dir).unwrap();
+    fs::write(husky_dir.join("pre-push"), "npm test\n").unwrap();
+
+    let hooks_dir = root.join(".cargo-husky").join("hooks");
+    fs::create_dir_all(&hooks_dir).unwrap();
+    let precommit = hooks_dir.join("pre-commit");
+    fs::write(&precommit, "#!/bin/sh\necho 'from cargo-husky'\n").unwrap();
+    fs::set_permissions(&precommit, fs::Permissions::from_mode(0o755)).unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(hook_path(&root, "pre-commit").is_file());
+    assert!(!hook_path(&root, "pre-push").exists());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn user_hooks_in_multiple_dirs() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("user-hooks-multiple-dirs");
+    setup_user_hooks_feature(&root);
+
+    let write_hook = |dir: &str, name: &str, content: &str| {
+        let dir = root.join(dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\necho '{}'\n", content)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    write_hook("shared/hooks", "pre-commit", "shared pre-commit");
+    write_hook("shared/hooks", "post-merge", "shared post-merge");
+    write_hook("local/hooks", "pre-commit", "local pre-commit");
+    write_hook("local/hooks", "pre-push", "local pre-push");
+
+    // Directory which does not exist is ignored
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[(
+            "CARGO_HUSKY_HOOKS_DIR",
+            "shared/hooks:not-exist:local/hooks",
+        )],
+    )
+    .unwrap();
+
+    // Earlier directory takes precedence
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(script.contains("shared pre-commit"), "{}", script);
+    let script = get_hook_script(&root, "post-merge").unwrap();
+    assert!(script.contains("shared post-merge"), "{}", script);
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script.contains("local pre-push"), "{}", script);
+
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let err = run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_HOOKS_DIR", "not-exist1:not-exist2")],
+    )
+    .unwrap_err();
+    assert!(
+        err.contains("User hooks directory is not found"),
+        "Unexpected output on `cargo test`: {}",
+        err
+    );
+}
+
+#[test]
+fn user_hooks_custom_dir_not_found() {
+    let root = cargo_project_for("user-hooks-custom-dir-not-found");
+    setup_user_hooks_feature(&root);
+
+    let err =
+        run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_HOOKS_DIR", "not-exist")]).unwrap_err();
+    assert!(
+        err.contains("User hooks directory is not found") && err.contains("not-exist"),
+        "Unexpected output on `cargo test`: {}",
+        err
+    );
+}
+
+#[test]
+fn empty_script_file_not_allowed() {
+    let root = cargo_project_for("empty-user-hook");
+    setup_user_hooks_feature(&root);
+
+    let user_hooks = TESTDIR.join("empty-user-hook");
+    copy_dir_recursive(&user_hooks.join(".cargo-husky"), &root.join(".cargo-husky"));
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(err.contains("User hook script is empty"));
+}
+
+#[test]
+fn commit_msg_hook() {
+    let root = cargo_project_for("commit-msg-hook");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"commit-msg-hook\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_COMMIT_MSG_COMMAND", "commitlint --edit")],
+    )
+    .unwrap();
+
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+
+    let script = get_hook_script(&root, "commit-msg").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "if [ \"$cargo_husky_status\" -ne 0 ]; then")
+            .count(),
+        1
+    );
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| *l == "commitlint --edit \"$@\"")
+            .count(),
+        1
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn commit_msg_hook_fails_when_command_fails() {
+    let root = cargo_project_for("commit-msg-hook-fails");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"commit-msg-hook\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_COMMIT_MSG_COMMAND", "grep -q '^feat: '")],
+    )
+    .unwrap();
+
+    let msg = root.join("COMMIT_EDITMSG");
+    let run_hook = || {
+        Command::new("sh")
+            .arg(hook_path(&root, "commit-msg"))
+            .arg(&msg)
+            .current_dir(&root)
+            .output()
+            .unwrap()
+            .status
+    };
+
+    writeln!(File::create(&msg).unwrap(), "feat: add something").unwrap();
+    assert!(run_hook().success());
+
+    writeln!(File::create(&msg).unwrap(), "add something").unwrap();
+    assert!(!run_hook().success());
+}
+
+#[test]
+fn commit_msg_hook_without_command() {
+    let root = cargo_project_for("commit-msg-hook-without-command");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"commit-msg-hook\"]"
+    )
+    .unwrap();
+
+    let err = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(err.contains("CARGO_HUSKY_COMMIT_MSG_COMMAND"));
+    assert_eq!(get_hook_script(&root, "commit-msg"), None);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn prepare_commit_msg_hook() {
+    let root = cargo_project_for("prepare-commit-msg-hook");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepare-commit-msg-hook\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[(
+            "CARGO_HUSKY_PREPARE_COMMIT_MSG_COMMAND",
+            "sh -c 'printf \"%s|\" \"$@\" > args.txt' prepare",
+        )],
+    )
+    .unwrap();
+
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+    assert_eq!(get_hook_script(&root, "commit-msg"), None);
+
+    let script = get_hook_script(&root, "prepare-commit-msg").unwrap();
+    assert!(script
+        .lines()
+        .nth(2)
+        .unwrap()
+        .contains(format!("set by cargo-husky v{}", env!("CARGO_PKG_VERSION")).as_str()));
+
+    // All arguments from Git are forwarded to the command
+    let status = Command::new("sh")
+        .arg(hook_path(&root, "prepare-commit-msg"))
+        .args([".git/COMMIT_EDITMSG", "commit", "HEAD"])
+        .current_dir(&root)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut args = String::new();
+    File::open(root.join("args.txt"))
+        .unwrap()
+        .read_to_string(&mut args)
+        .unwrap();
+    assert_eq!(args, ".git/COMMIT_EDITMSG|commit|HEAD|");
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn report_failed_command() {
+    let root = cargo_project_for("report-failed-command");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nhooks.pre-commit = [\"true\", \"false\", \"echo unreachable\"]\nhooks.pre-push = [\"true\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let run_hook = |hook| {
+        Command::new("sh")
+            .arg(hook_path(&root, hook))
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+
+    let out = run_hook("pre-commit");
+    assert!(!out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("cargo-husky: 'false' failed with exit code 1, aborting"),
+        "Unexpected stderr: {}",
+        stderr
+    );
+    assert!(!String::from_utf8(out.stdout)
+        .unwrap()
+        .contains("unreachable"));
+
+    let out = run_hook("pre-push");
+    assert!(out.status.success());
+    assert!(!String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("cargo-husky:"));
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn skip_precommit_during_rebase() {
+    let root = cargo_project_for("skip-during-rebase");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"features = ["skip-during-rebase"]
+
+[package.metadata.cargo-husky]
+hooks.pre-commit = ["false"]
+hooks.pre-push = ["false"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let run_hook = |hook| {
+        Command::new("sh")
+            .arg(hook_path(&root, hook))
+            .current_dir(&root)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    assert!(!run_hook("pre-commit").status.success());
+
+    for dir in &["rebase-merge", "rebase-apply"] {
+        let marker = root.join(".git").join(dir);
+        fs::create_dir(&marker).unwrap();
+        let out = run_hook("pre-commit");
+        assert!(out.status.success(), "{:?}", out);
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        assert!(stdout.contains("rebase is in progress"), "{}", stdout);
+        // Other hooks are not skipped
+        assert!(!run_hook("pre-push").status.success());
+        fs::remove_dir(&marker).unwrap();
+    }
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn run_pre_and_post_commands() {
+    let root = cargo_project_for("pre-post-command");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"features = ["precommit-hook"]
+
+[package.metadata.cargo-husky]
+hooks.pre-commit = ["echo main", "false"]"#
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[
+            ("CARGO_HUSKY_PRE_COMMAND", "echo setup"),
+            ("CARGO_HUSKY_POST_COMMAND", "echo 'tear down'"),
+        ],
+    )
+    .unwrap();
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    // Exit status of the failed command is kept
+    assert_eq!(out.status.code(), Some(1));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let outputs: Vec<_> = stdout.lines().filter(|l| !l.starts_with('+')).collect();
+    assert_eq!(outputs, ["setup", "main", "tear down"], "{}", stdout);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn no_fail_fast() {
+    let root = cargo_project_for("no-fail-fast");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"features = ["no-fail-fast"]
+
+[package.metadata.cargo-husky]
+hooks.pre-commit = ["false", "echo reachable", "sh -c 'exit 3'"]
+hooks.pre-push = ["true"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let run_hook = |hook| {
+        Command::new("sh")
+            .arg(hook_path(&root, hook))
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+
+    let out = run_hook("pre-commit");
+    assert_eq!(out.status.code(), Some(1));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("reachable"), "{}", stdout);
+    // Echo of each command is kept
+    assert!(stdout.contains("+sh -c 'exit 3'"), "{}", stdout);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("cargo-husky: 'false' failed with exit code 1, continuing")
+            && stderr.contains("cargo-husky: 'sh -c 'exit 3'' failed with exit code 3, continuing")
+            && stderr.contains("cargo-husky: 2 command(s) failed"),
+        "Unexpected stderr: {}",
+        stderr
+    );
+
+    assert!(run_hook("pre-push").status.success());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn no_fail_fast_in_parallel() {
+    let root = cargo_project_for("no-fail-fast-parallel");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"features = ["no-fail-fast", "parallel"]
+
+[package.metadata.cargo-husky]
+hooks.pre-commit = ["false", "echo second"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("second"), "{}", stdout);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn print_banner_at_start() {
+    let root = cargo_project_for("banner");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"precommit-hook\", \"banner\"]\n\n[package.metadata.cargo-husky]\nhooks.pre-commit = [\"echo checked\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        stdout.starts_with("cargo-husky: Running pre-commit checks...\n"),
+        "{}",
+        stdout
+    );
+
+    // Banner is omitted in quiet mode
+    let root = cargo_project_for("banner-quiet");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"banner\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_QUIET", "1")]).unwrap();
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(!script.contains("Running pre-push checks"), "{}", script);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn print_timings_of_commands() {
+    let root = cargo_project_for("timings");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"features = ["timings"]
+
+[package.metadata.cargo-husky]
+hooks.pre-commit = ["echo first", "sleep 1 && false"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("cargo-husky: echo first: "), "{}", stdout);
+    // Duration is shown even if the command failed
+    let line = stdout
+        .lines()
+        .find(|l| l.starts_with("cargo-husky: sleep 1 && false: "))
+        .unwrap_or_else(|| panic!("{}", stdout));
+    let secs: u64 = line
+        .trim_start_matches("cargo-husky: sleep 1 && false: ")
+        .trim_end_matches('s')
+        .parse()
+        .unwrap();
+    assert!(secs >= 1, "{}", line);
+}
+
+#[test]
+fn hooks_dir_is_exposed_to_crate() {
+    let root = cargo_project_for("hooks-dir-exposed");
+    fs::create_dir(root.join("tests")).unwrap();
+    writeln!(
+        File::create(root.join("tests").join("hooks_dir.rs")).unwrap(),
+        "#[test]\nfn print_hooks_dir() {{\n    println!(\"hooks dir: {{:?}}\", cargo_husky::hooks_dir());\n}}"
+    )
+    .unwrap();
+
+    let out = run_cargo(&root, ["test", "--", "--nocapture"]).unwrap();
+    let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+    let hooks_dir = fs::canonicalize(root.join(".git").join("hooks")).unwrap();
+    let expected = format!("hooks dir: Some({:?})", hooks_dir.to_str().unwrap());
+    assert!(stdout.contains(&expected), "{}", stdout);
+
+    // Not exposed when installation was skipped
+    writeln!(
+        open_cargo_toml(&root),
+        "\n[package.metadata.cargo-husky]\nskip = true"
+    )
+    .unwrap();
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let out = run_cargo(&root, ["test", "--", "--nocapture"]).unwrap();
+    let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+    assert!(stdout.contains("hooks dir: None"), "{}", stdout);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn load_dotenv() {
+    let root = cargo_project_for("load-dotenv");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"features = ["load-dotenv"]
+
+[package.metadata.cargo-husky]
+hooks.pre-commit = ['echo "FOO=$FOO BAR=$BAR QUOTED=$QUOTED EXISTING=$EXISTING CRLF=$CRLF."']"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let run_hook = || {
+        let out = Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .env("EXISTING", "from-env")
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    // Missing .env is ignored
+    let stdout = run_hook();
+    assert!(
+        stdout.contains("FOO= BAR= QUOTED= EXISTING=from-env CRLF=."),
+        "{}",
+        stdout
+    );
+
+    fs::write(
+        root.join(".env"),
+        "# comment\n\nFOO=foo\nexport BAR=\"bar baz\"\nQUOTED='single'\nmalformed line\n1BAD=x\nEXISTING=from-dotenv\nCRLF=crlf\r\nLAST=no-newline",
+    )
+    .unwrap();
+    let stdout = run_hook();
+    assert!(
+        stdout.contains("FOO=foo BAR=bar baz QUOTED=single EXISTING=from-env CRLF=crlf."),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn verbose_output() {
+    let root = cargo_project_for("verbose-output");
+    let verbose = [("CARGO_HUSKY_VERBOSE", "1")];
+    let out = run_cargo_with_env(&root, ["test"], &verbose).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("Installing hooks enabled by features"),
+        "Unexpected stderr: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "Wrote hook {}",
+            hook_path(&root, "pre-push").display()
+        )),
+        "Unexpected stderr: {}",
+        stderr
+    );
+
+    // Build script is run again after removing target directory
+    fs::remove_dir_all(root.join("target")).unwrap();
+    let out = run_cargo_with_env(&root, ["test"], &verbose).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!(
+            "Skipped {}: already generated by cargo-husky v{}",
+            hook_path(&root, "pre-push").display(),
+            env!("CARGO_PKG_VERSION"),
+        )),
+        "Unexpected stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn skip_hook_at_runtime() {
+    let root = cargo_project_for("skip-at-runtime");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nhooks.pre-commit = [\"false\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let run_hook = |skip| {
+        Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .env("CARGO_HUSKY_SKIP", skip)
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+
+    for skip in &["1", "true", "TRUE", "True"] {
+        let out = run_hook(skip);
+        assert!(out.status.success(), "{:?}", skip);
+        let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+        assert!(stdout.contains("Skipped"), "{:?}: {}", skip, stdout);
+    }
+    for skip in &["", "0", "false", "yes"] {
+        assert!(!run_hook(skip).status.success(), "{:?}", skip);
+    }
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn postmerge_hook_only_when_deps_changed() {
+    let root = cargo_project_for("deps-changed-only");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"default-features = false
+features = ["postmerge-hook", "run-cargo-build", "deps-changed-only"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "post-merge").unwrap();
+    assert_eq!(script.lines().filter(|l| *l == "cargo build").count(), 1);
+
+    // Replace the command to check whether it is run without building the project again
+    let script = script.replace("\ncargo build\n", "\necho 'building'\n");
+    fs::write(hook_path(&root, "post-merge"), script).unwrap();
+
+    set_git_config(&root, "user.name", "cargo-husky");
+    set_git_config(&root, "user.email", "cargo-husky@example.com");
+    run_git(&root, ["add", "."]);
+    run_git(&root, ["commit", "-q", "-m", "initial"]);
+
+    let run_hook = |squash| {
+        let out = Command::new("sh")
+            .arg(hook_path(&root, "post-merge"))
+            .arg(squash)
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    // Merge which does not change dependencies
+    run_git(&root, ["update-ref", "ORIG_HEAD", "HEAD"]);
+    fs::write(root.join("README.md"), "hello\n").unwrap();
+    run_git(&root, ["add", "README.md"]);
+    run_git(&root, ["commit", "-q", "-m", "readme"]);
+    let stdout = run_hook("0");
+    assert!(stdout.contains("Skipped"), "{}", stdout);
+    assert!(!stdout.contains("building"), "{}", stdout);
+
+    // Merge which changes Cargo.lock
+    run_git(&root, ["update-ref", "ORIG_HEAD", "HEAD"]);
+    fs::write(root.join("Cargo.lock"), "# changed\n").unwrap();
+    run_git(&root, ["add", "-f", "Cargo.lock"]);
+    run_git(&root, ["commit", "-q", "-m", "lock"]);
+    let stdout = run_hook("0");
+    assert!(stdout.contains("building"), "{}", stdout);
+
+    // Squash merge only updates the index
+    let stdout = run_hook("1");
+    assert!(stdout.contains("Skipped"), "{}", stdout);
+    writeln!(open_cargo_toml(&root), "# changed").unwrap();
+    run_git(&root, ["add", "Cargo.toml"]);
+    let stdout = run_hook("1");
+    assert!(stdout.contains("building"), "{}", stdout);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn run_commands_in_parallel() {
+    let root = cargo_project_for("parallel");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        r#"features = ["parallel"]
+
+[package.metadata.cargo-husky]
+hooks.pre-commit = ["sleep 1; echo first", "echo second", "sleep 1; false"]
+hooks.pre-push = ["sleep 1", "sleep 1", "sleep 1"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let run_hook = |hook| {
+        Command::new("sh")
+            .arg(hook_path(&root, hook))
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+
+    let out = run_hook("pre-commit");
+    assert!(!out.status.success());
+    // Outputs are shown in order of commands
+    let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "+sleep 1; echo first",
+            "first",
+            "+echo second",
+            "second",
+            "+sleep 1; false",
+            "cargo-husky: 'sleep 1; false' failed with exit code 1, aborting",
+        ]
+    );
+
+    let start = time::Instant::now();
+    assert!(run_hook("pre-push").status.success());
+    assert!(start.elapsed() < time::Duration::from_millis(2500));
+}
+
+#[test]
+fn git_dir_env_var() {
+    let root = cargo_project_for("git-dir-env-var");
+    let gitdir = tmpdir_for("git-dir-env-var-gitdir");
+    fs::create_dir(gitdir.join("hooks")).unwrap();
+
+    run_cargo_with_env(&root, ["test"], &[("GIT_DIR", gitdir.to_str().unwrap())]).unwrap();
+
+    assert!(gitdir.join("hooks").join("pre-push").is_file());
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn fix_staged_files_by_clippy() {
+    let root = cargo_project_for("clippy-fix");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"precommit-hook\", \"prepush-hook\", \"run-cargo-check\", \"clippy-fix\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(script.contains("cargo clippy --fix"), "{}", script);
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(!script.contains("cargo clippy --fix"), "{}", script);
+
+    let run_hook = || {
+        let out = Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let git_status = || {
+        let out = Command::new("git")
+            .args(["status", "--porcelain", "--", "src"])
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let lib_rs = root.join("src").join("lib.rs");
+    fs::write(
+        &lib_rs,
+        "mod other;\npub fn f(v: &[i32]) -> bool {\n    v.len() == 0\n}\n",
+    )
+    .unwrap();
+    // Not staged. It should not be staged by the hook even if it is fixed
+    fs::write(
+        root.join("src").join("other.rs"),
+        "pub fn g(v: &[i32]) -> bool {\n    v.len() == 0\n}\n",
+    )
+    .unwrap();
+    run_git(&root, ["add", "src/lib.rs"]);
+
+    run_hook();
+    assert_eq!(git_status(), "A  src/lib.rs\n?? src/other.rs\n");
+    let staged = fs::read_to_string(&lib_rs).unwrap();
+    assert!(staged.contains("v.is_empty()"), "{}", staged);
+
+    // Unstaged changes would be mixed with the fixes
+    fs::write(
+        &lib_rs,
+        "mod other;\npub fn f(v: &[i32]) -> bool {\n    v.len() == 0\n}\n",
+    )
+    .unwrap();
+    let stdout = run_hook();
+    assert!(
+        stdout.contains("since Rust files have unstaged changes"),
+        "{}",
+        stdout
+    );
+    assert_eq!(git_status(), "AM src/lib.rs\n?? src/other.rs\n");
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn fix_staged_files_by_rustfmt() {
+    let root = cargo_project_for("fmt-fix");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"precommit-hook\", \"run-for-all\", \"fmt-fix\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .filter(|l| l.trim() == "cargo fmt --all")
+            .count(),
+        1
+    );
+
+    let run_hook = || {
+        let out = Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let git_status = || {
+        let out = Command::new("git")
+            .args(["status", "--porcelain", "--", "src"])
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let lib_rs = root.join("src").join("lib.rs");
+    fs::write(&lib_rs, "mod other;\npub fn f( )->i32{1}\n").unwrap();
+    // Not staged. It should not be staged by the hook even if it is formatted
+    fs::write(root.join("src").join("other.rs"), "pub fn g( )->i32{2}\n").unwrap();
+    run_git(&root, ["add", "src/lib.rs"]);
+
+    let stdout = run_hook();
+    assert_eq!(git_status(), "A  src/lib.rs\n?? src/other.rs\n");
+    let staged = fs::read_to_string(&lib_rs).unwrap();
+    assert_eq!(staged, "mod other;\npub fn f() -> i32 {\n    1\n}\n");
+    assert!(!stdout.contains("also changed"), "{}", stdout);
+
+    // Nothing is staged again when formatting changes nothing
+    set_git_config(&root, "user.name", "cargo-husky");
+    set_git_config(&root, "user.email", "cargo-husky@example.com");
+    run_git(&root, ["add", "src/other.rs"]);
+    run_git(&root, ["commit", "-q", "--no-verify", "-m", "initial"]);
+    fs::write(&lib_rs, "mod other;\npub fn f() -> i32 {\n    3\n}\n").unwrap();
+    run_git(&root, ["add", "src/lib.rs"]);
+    run_hook();
+    assert_eq!(git_status(), "M  src/lib.rs\n");
+
+    // Unstaged changes would be swept up with the formatting
+    fs::write(&lib_rs, "mod other;\npub fn f( )->i32{4}\n").unwrap();
+    let stdout = run_hook();
+    assert!(
+        stdout.contains("since Rust files have unstaged changes"),
+        "{}",
+        stdout
+    );
+    assert_eq!(git_status(), "MM src/lib.rs\n");
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn hints_on_failure() {
+    let root = cargo_project_for("failure-hints");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"precommit-hook\", \"run-cargo-fmt\", \"run-cargo-clippy\", \"failure-hints\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(
+        script.contains("Run `cargo clippy --fix` to fix some of the warnings automatically"),
+        "{}",
+        script
+    );
+
+    fs::write(root.join("src").join("lib.rs"), "pub fn f( )->i32{1}\n").unwrap();
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("cargo-husky: Run `cargo fmt` to fix formatting"),
+        "{}",
+        stderr
+    );
+
+    // Hints are not shown without the feature
+    let root = cargo_project_for("no-failure-hints");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"precommit-hook\", \"run-cargo-fmt\", \"run-cargo-clippy\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(!script.contains("to fix"), "{}", script);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn retry_flaky_commands() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("retry-commands");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-check\", \"run-cargo-test\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_RETRIES", "2")]).unwrap();
+
+    // Stub cargo which records its arguments and fails the first $STUB_FAILS runs of `cargo test`
+    let bin = root.join("stub-bin");
+    fs::create_dir_all(&bin).unwrap();
+    let log = root.join("stub-cargo.log");
+    let stub = bin.join("cargo");
+    fs::write(
+        &stub,
+        format!(
+            "#!/bin/sh\necho \"$*\" >> '{log}'\nif [ \"$1\" = test ] && [ \"$(grep -c '^test$' '{log}')\" -le \"$STUB_FAILS\" ]; then\n    exit 101\nfi\n",
+            log = log.display(),
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let run_hook = |fails: &str| {
+        let _ = fs::remove_file(&log);
+        let path = format!("{}:{}", bin.display(), env::var("PATH").unwrap());
+        let out = Command::new("sh")
+            .arg(hook_path(&root, "pre-push"))
+            .current_dir(&root)
+            .env("PATH", path)
+            .env("STUB_FAILS", fails)
+            .output()
+            .unwrap();
+        let runs = fs::read_to_string(&log).unwrap();
+        (out, runs)
+    };
+
+    let (out, runs) = run_hook("1");
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(runs, "check\ntest\ntest\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "cargo-husky: 'cargo test' failed with exit code 101, retrying (attempt 2 of 3)"
+        ),
+        "{}",
+        stderr
+    );
+
+    // The hook fails after all attempts failed
+    let (out, runs) = run_hook("3");
+    assert_eq!(out.status.code(), Some(101), "{:?}", out);
+    assert_eq!(runs, "check\ntest\ntest\ntest\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("(attempt 3 of 3)"), "{}", stderr);
+    assert!(
+        stderr.contains("cargo-husky: 'cargo test' failed with exit code 101, aborting"),
+        "{}",
+        stderr
+    );
+
+    // Commands other than tests are not retried by default
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script.matches("cargo_husky_attempt=1").count(),
+        1,
+        "{}",
+        script
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn disable_color_of_commands() {
+    let root = cargo_project_for("no-color");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"precommit-hook\", \"no-color\"]\n\n[package.metadata.cargo-husky]\nhooks.pre-commit = ['echo \"color=$CARGO_TERM_COLOR no_color=$NO_COLOR\"']"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .env("CARGO_TERM_COLOR", "always")
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("\ncolor=never no_color=1\n"), "{}", stdout);
+
+    // Nothing is exported without the feature
+    let root = cargo_project_for("color");
+    run_cargo(&root, ["test"]).unwrap();
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(!script.contains("CARGO_TERM_COLOR"), "{}", script);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn override_cargo_binary() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("override-cargo");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-doc\", \"run-cargo-test\"]"
+    )
+    .unwrap();
+
+    // Cargo at a path which is not in $PATH and needs quoting
+    let bin = root.join("my bin");
+    fs::create_dir_all(&bin).unwrap();
+    let log = root.join("stub-cargo.log");
+    let stub = bin.join("cargo");
+    fs::write(
+        &stub,
+        format!(
+            "#!/bin/sh\necho \"$RUSTDOCFLAGS:$*\" >> '{}'\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_CARGO", stub.to_str().unwrap())],
+    )
+    .unwrap();
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(
+        script.contains(&format!("\n'{}' test\n", stub.display())),
+        "{}",
+        script
+    );
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-push"))
+        .current_dir(&root)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "-D warnings:doc --no-deps\n:test\n"
+    );
+
+    // Empty value is ignored with a warning
+    fs::remove_dir_all(root.join("target")).unwrap();
+    fs::remove_file(hook_path(&root, "pre-push")).unwrap();
+    let out = run_cargo_with_env(&root, ["test", "-vv"], &[("CARGO_HUSKY_CARGO", " ")]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("'$CARGO_HUSKY_CARGO' must be a non-empty path or name of cargo"),
+        "{}",
+        stderr
+    );
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script.contains("\ncargo test\n"), "{}", script);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn add_cargo_bin_to_path() {
+    let root = cargo_project_for("fix-path");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"fix-path\"]\n\n[package.metadata.cargo-husky]\nhooks.pre-push = ['echo \"PATH=$PATH\"']"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let home = root.join("home");
+    let cargo_home = root.join("cargo-home");
+    fs::create_dir_all(&home).unwrap();
+    let run_hook = |cargo_home: Option<&Path>| {
+        let mut cmd = Command::new("sh");
+        cmd.arg(hook_path(&root, "pre-push"))
+            .current_dir(&root)
+            .stdin(Stdio::null())
+            .env("HOME", &home)
+            .env("PATH", "/usr/bin:/bin");
+        match cargo_home {
+            Some(dir) => cmd.env("CARGO_HOME", dir),
+            None => cmd.env_remove("CARGO_HOME"),
+        };
+        let out = cmd.output().unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        stdout
+            .lines()
+            .find_map(|l| l.strip_prefix("PATH="))
+            .unwrap()
+            .to_string()
+    };
+
+    // Directories which don't exist are not added
+    assert_eq!(run_hook(None), "/usr/bin:/bin");
+    assert_eq!(run_hook(Some(&cargo_home)), "/usr/bin:/bin");
+
+    let home_bin = home.join(".cargo").join("bin");
+    fs::create_dir_all(&home_bin).unwrap();
+    assert_eq!(
+        run_hook(None),
+        format!("/usr/bin:/bin:{}", home_bin.display())
+    );
+
+    let cargo_home_bin = cargo_home.join("bin");
+    fs::create_dir_all(&cargo_home_bin).unwrap();
+    assert_eq!(
+        run_hook(Some(&cargo_home)),
+        format!(
+            "/usr/bin:/bin:{}:{}",
+            cargo_home_bin.display(),
+            home_bin.display()
+        )
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn check_posix_strict_hooks() {
+    let root = cargo_project_for("posix-strict");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"run-cargo-test\", \"run-cargo-fmt\", \"posix-strict\", \"timings\", \"load-dotenv\", \"fix-path\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert!(script.contains("cargo test"), "{}", script);
+
+    // Broken commands configured by users are also rejected and the hook is not overwritten
+    fs::remove_dir_all(root.join("target")).unwrap();
+    fs::remove_file(hook_path(&root, "pre-push")).unwrap();
+    writeln!(
+        open_cargo_toml(&root),
+        "\n[package.metadata.cargo-husky]\nhooks.pre-push = ['if true; then echo unterminated']"
+    )
+    .unwrap();
+    let stderr = run_cargo(&root, ["test"]).unwrap_err();
+    assert!(
+        stderr.contains("Generated hook script was rejected by `sh` as POSIX sh"),
+        "{}",
+        stderr
+    );
+    assert!(get_hook_script(&root, "pre-push").is_none());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn fail_when_cargo_component_is_missing() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = cargo_project_for("missing-component");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"precommit-hook\", \"run-cargo-clippy\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    // Stub cargo which behaves as if clippy component were not installed
+    let bin = root.join("stub-bin");
+    fs::create_dir_all(&bin).unwrap();
+    let stub = bin.join("cargo");
+    fs::write(
+        &stub,
+        "#!/bin/sh\nif [ \"$1\" = clippy ]; then\n    echo 'error: no such command: `clippy`' >&2\n    exit 101\nfi\n",
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!("{}:{}", bin.display(), env::var("PATH").unwrap());
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "cargo-husky: `cargo clippy` is not installed. Install it by `rustup component add clippy`"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("no such command"), "{}", stderr);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_changed_modules_only() {
+    let root = cargo_project_for("test-changed");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"precommit-hook\", \"run-cargo-test\", \"test-changed\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let src = root.join("src");
+    fs::write(src.join("lib.rs"), "mod foo;\nmod bar;\n").unwrap();
+    fs::create_dir_all(src.join("foo")).unwrap();
+    fs::write(
+        src.join("foo").join("mod.rs"),
+        "mod baz;\n#[test]\nfn test_foo() {}\n",
+    )
+    .unwrap();
+    fs::write(
+        src.join("foo").join("baz.rs"),
+        "#[test]\nfn test_baz() {}\n",
+    )
+    .unwrap();
+    fs::write(src.join("bar.rs"), "#[test]\nfn test_bar() {}\n").unwrap();
+
+    let run_hook = || {
+        let out = Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    run_git(&root, ["add", "src/bar.rs", "src/foo/baz.rs"]);
+    let stdout = run_hook();
+    assert!(
+        stdout.contains("+cargo test -- $cargo_husky_test_filters"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("test bar::test_bar ... ok"), "{}", stdout);
+    assert!(
+        stdout.contains("test foo::baz::test_baz ... ok"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("test_foo"), "{}", stdout);
+
+    // Tests in submodules are also run
+    run_git(&root, ["reset", "-q"]);
+    run_git(&root, ["add", "src/foo/mod.rs"]);
+    let stdout = run_hook();
+    assert!(stdout.contains("test foo::test_foo ... ok"), "{}", stdout);
+    assert!(
+        stdout.contains("test foo::baz::test_baz ... ok"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("test_bar"), "{}", stdout);
+
+    // Crate root cannot be mapped to a module so all tests are run
+    run_git(&root, ["add", "src/lib.rs"]);
+    let stdout = run_hook();
+    assert!(stdout.contains("+cargo test\n"), "{}", stdout);
+    assert!(stdout.contains("test bar::test_bar ... ok"), "{}", stdout);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn forbid_large_files_in_staged_files() {
+    let root = cargo_project_for("forbid-large-files");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"precommit-hook\", \"forbid-large-files\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_MAX_FILE_SIZE", "1K")]).unwrap();
+
+    let run_hook = || {
+        Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+
+    fs::write(root.join("small.bin"), vec![0u8; 1024]).unwrap();
+    fs::write(root.join("large file.bin"), vec![0u8; 1025]).unwrap();
+    run_git(&root, ["add", "small.bin"]);
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+
+    run_git(&root, ["add", "large file.bin"]);
+    let out = run_hook();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("large file.bin (1025 bytes)"), "{}", stderr);
+    assert!(!stderr.contains("small.bin"), "{}", stderr);
+    assert!(
+        stderr.contains("Staged files larger than 1024 bytes were found"),
+        "{}",
+        stderr
+    );
+
+    // Deleting the large file is not blocked
+    set_git_config(&root, "user.name", "cargo-husky");
+    set_git_config(&root, "user.email", "cargo-husky@example.com");
+    run_git(&root, ["commit", "-q", "--no-verify", "-m", "large"]);
+    run_git(&root, ["rm", "-q", "large file.bin"]);
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+
+    // Renamed file is also checked
+    run_git(&root, ["reset", "-q", "--hard"]);
+    run_git(&root, ["mv", "large file.bin", "moved.bin"]);
+    let out = run_hook();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("moved.bin (1025 bytes)"), "{}", stderr);
+}
 
-    run_cargo(&root, &["test"]).unwrap();
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn json_report_of_commands() {
+    let root = cargo_project_for("json-report");
+    writeln!(
+        open_cargo_toml(&root),
+        r#"default-features = false
+features = ["precommit-hook", "json-report"]
 
-    assert!(!hook_path(&root, "pre-push").exists()); // Default features are ignored
-    assert!(hook_path(&root, "pre-commit").is_file());
-    assert!(hook_path(&root, "post-merge").is_file());
+[package.metadata.cargo-husky]
+hooks.pre-commit = ['echo "a\b"', "sh -c 'exit 3'", "echo unreachable"]"#
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
-    let check_line = format!(
-        "# This hook was set by cargo-husky v{}: {}",
-        env!("CARGO_PKG_VERSION"),
-        env!("CARGO_PKG_HOMEPAGE")
+    let run_hook = |report: Option<&Path>| {
+        let mut cmd = Command::new("sh");
+        cmd.arg(hook_path(&root, "pre-commit")).current_dir(&root);
+        match report {
+            Some(path) => cmd.env("CARGO_HUSKY_REPORT", path),
+            None => cmd.env_remove("CARGO_HUSKY_REPORT"),
+        };
+        let out = cmd.output().unwrap();
+        assert_eq!(out.status.code(), Some(3), "{:?}", out);
+    };
+
+    let report = root.join("report.jsonl");
+    run_hook(Some(&report));
+    run_hook(Some(&report));
+    let content = fs::read_to_string(&report).unwrap();
+    let lines = content.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4, "{}", content);
+    assert!(
+        lines[0]
+            .starts_with(r#"{"hook":"pre-commit","command":"echo \"a\\b\"","status":0,"start":"#),
+        "{}",
+        content
+    );
+    assert!(
+        lines[1]
+            .starts_with(r#"{"hook":"pre-commit","command":"sh -c 'exit 3'","status":3,"start":"#),
+        "{}",
+        content
+    );
+    assert!(lines[1].contains(r#","duration":"#), "{}", content);
+    assert!(lines[1].ends_with('}'), "{}", content);
+    // Records are appended by each run
+    assert!(
+        lines[2].starts_with(r#"{"hook":"pre-commit","command":"echo"#),
+        "{}",
+        content
     );
 
-    let s = get_hook_script(&root, "pre-commit").unwrap();
-    assert_eq!(s.lines().nth(0), Some("#! /bin/sh"));
-    assert_eq!(s.lines().nth(2), Some(check_line.as_str()));
-    assert_eq!(
-        s.lines().nth(4),
-        Some("# This is a user script for pre-commit hook with shebang")
+    // Nothing is written when $CARGO_HUSKY_REPORT is not set
+    fs::remove_file(&report).unwrap();
+    run_hook(None);
+    assert!(!report.exists());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn forbid_patterns_in_staged_files() {
+    let root = cargo_project_for("forbid-patterns");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"precommit-hook\", \"forbid-patterns\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_HUSKY_FORBIDDEN_PATTERNS", "dbg!, println!,")],
+    )
+    .unwrap();
+
+    let run_hook = || {
+        Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+
+    // Nothing is checked when no Rust file is staged
+    fs::write(
+        root.join("src").join("lib.rs"),
+        "pub fn f() {\n    dbg!(1);\n}\n",
+    )
+    .unwrap();
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+
+    run_git(&root, ["add", "src/lib.rs"]);
+    let out = run_hook();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("src/lib.rs:2:    dbg!(1);"), "{}", stderr);
+    assert!(
+        stderr.contains("Forbidden patterns were found in staged files: dbg!, println!"),
+        "{}",
+        stderr
     );
 
-    let s = get_hook_script(&root, "post-merge").unwrap();
-    assert_eq!(s.lines().nth(0), Some("#"));
-    assert_eq!(s.lines().nth(2), Some(check_line.as_str()));
+    // Contents in the index are checked, not the working tree
+    fs::write(root.join("src").join("lib.rs"), "pub fn f() {}\n").unwrap();
+    let out = run_hook();
+    assert!(!out.status.success(), "{:?}", out);
+
+    run_git(&root, ["add", "src/lib.rs"]);
+    fs::write(root.join("src").join("other.rs"), "fn g() { dbg!(2); }\n").unwrap();
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn require_up_to_date_lockfile_on_push() {
+    let root = cargo_project_for("require-locked");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"prepush-hook\", \"precommit-hook\", \"run-cargo-check\", \"require-locked\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(!script.contains("cargo tree --locked"), "{}", script);
+
+    let run_hook = || {
+        Command::new("sh")
+            .arg(hook_path(&root, "pre-push"))
+            .current_dir(&root)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+
+    // Version of the root package recorded in Cargo.lock no longer matches
+    let manifest = fs::read_to_string(root.join("Cargo.toml")).unwrap();
+    let manifest = manifest.replacen("version = \"0.1.0\"", "version = \"0.2.0\"", 1);
+    fs::write(root.join("Cargo.toml"), manifest).unwrap();
+
+    let out = run_hook();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("Cargo.lock is out of date. Run `cargo update`"),
+        "{}",
+        stderr
+    );
+    // Failed before running commands
+    let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+    assert!(!stdout.contains("+cargo check"), "{}", stdout);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn require_clean_tree_on_push() {
+    let root = cargo_project_for("require-clean-tree");
+    writeln!(
+        open_cargo_toml(&root),
+        "default-features = false\nfeatures = [\"prepush-hook\", \"require-clean-tree\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    set_git_config(&root, "user.name", "cargo-husky");
+    set_git_config(&root, "user.email", "cargo-husky@example.com");
+    run_git(&root, ["add", "."]);
+    run_git(&root, ["commit", "-q", "-m", "initial"]);
+
+    let run_hook = || {
+        Command::new("sh")
+            .arg(hook_path(&root, "pre-push"))
+            .current_dir(&root)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+
+    fs::write(root.join("src").join("lib.rs"), "// changed\n").unwrap();
+    let out = run_hook();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(stderr.contains(" M src/lib.rs\n"), "{}", stderr);
+    assert!(
+        stderr.contains("Working tree has uncommitted changes"),
+        "{}",
+        stderr
+    );
+
+    // Skipped while rebase is in progress
+    fs::create_dir(root.join(".git").join("rebase-merge")).unwrap();
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+    fs::remove_dir(root.join(".git").join("rebase-merge")).unwrap();
+
+    run_git(&root, ["checkout", "-q", "--", "src/lib.rs"]);
+    fs::write(root.join("untracked.txt"), "").unwrap();
+    let out = run_hook();
+    assert!(!out.status.success(), "{:?}", out);
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(stderr.contains("?? untracked.txt\n"), "{}", stderr);
+
+    // Untracked files can be allowed
+    fs::remove_dir_all(root.join("target")).unwrap();
+    fs::remove_file(hook_path(&root, "pre-push")).unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_ALLOW_UNTRACKED", "1")]).unwrap();
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn check_format_of_staged_files_only() {
+    let root = cargo_project_for("staged-only");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "default-features = false\nfeatures = [\"precommit-hook\", \"prepush-hook\", \"run-cargo-fmt\", \"run-cargo-check\", \"staged-only\"]"
+    )
+    .unwrap();
+    run_cargo(&root, ["test"]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    // Commands which do not accept files run on the whole project
     assert_eq!(
-        s.lines().nth(3),
-        Some("# Script without shebang (I'm not sure this is useful)")
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo check")
+            .count(),
+        1
+    );
+    // Other hooks check the whole project
+    let script = get_hook_script(&root, "pre-push").unwrap();
+    assert_eq!(
+        script
+            .lines()
+            .map(str::trim_start)
+            .filter(|l| *l == "cargo fmt -- --check")
+            .count(),
+        1
     );
+
+    let run_hook = || {
+        Command::new("sh")
+            .arg(hook_path(&root, "pre-commit"))
+            .current_dir(&root)
+            .output()
+            .unwrap()
+    };
+    let git_add = |file| {
+        let status = Command::new("git")
+            .args(["add", file])
+            .current_dir(&root)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    // Badly formatted but not staged
+    writeln!(
+        File::create(root.join("src").join("lib.rs")).unwrap(),
+        "pub fn f( ) {{}}"
+    )
+    .unwrap();
+
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = str::from_utf8(out.stdout.as_slice()).unwrap();
+    assert!(stdout.contains("No Rust file is staged"), "{}", stdout);
+
+    writeln!(
+        File::create(root.join("src").join("good.rs")).unwrap(),
+        "pub fn g() {{}}"
+    )
+    .unwrap();
+    git_add("src/good.rs");
+    let out = run_hook();
+    assert!(out.status.success(), "{:?}", out);
+
+    git_add("src/lib.rs");
+    assert!(!run_hook().status.success());
 }
 
-fn assert_user_hooks_error(root: &Path) {
-    match run_cargo(&root, &["test"]) {
-        Ok(out) => assert!(
-            false,
-            "`cargo test` has unexpectedly successfully done: {:?}",
-            out
-        ),
-        Err(err) => assert!(
-            format!("{}", err)
-                .contains("User hooks directory is not found or no executable file is found in"),
-            "Unexpected output on `cargo test`: {}",
-            err
-        ),
-    }
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn quiet_mode() {
+    let root = cargo_project_for("quiet-mode");
+    let mut cargo_toml = open_cargo_toml(&root);
+    writeln!(
+        cargo_toml,
+        "\n[package.metadata.cargo-husky]\nhooks.pre-commit = [\"echo ok\", \"false\"]"
+    )
+    .unwrap();
+    run_cargo_with_env(&root, ["test"], &[("CARGO_HUSKY_QUIET", "1")]).unwrap();
+
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert!(!script.lines().any(|l| l.starts_with("echo '+")));
+
+    let out = Command::new("sh")
+        .arg(hook_path(&root, "pre-commit"))
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert_eq!(str::from_utf8(out.stdout.as_slice()).unwrap(), "ok\n");
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("cargo-husky: 'false' failed with exit code 1"),
+        "Unexpected stderr: {}",
+        stderr
+    );
 }
 
 #[test]
-fn user_hooks_dir_not_found() {
-    let root = cargo_project_for("user-hooks-dir-not-found");
-    setup_user_hooks_feature(&root);
-    assert_user_hooks_error(&root);
+fn absolute_path_in_git_file() {
+    let root = cargo_project_for("gitfile-absolute");
+    let gitdir = tmpdir_for("gitfile-absolute-gitdir").join("repo.git");
+    fs::rename(root.join(".git"), &gitdir).unwrap();
+    writeln!(
+        File::create(root.join(".git")).unwrap(),
+        "gitdir: {}",
+        gitdir.to_string_lossy()
+    )
+    .unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(gitdir.join("hooks").join("pre-push").is_file());
 }
 
 #[test]
-fn user_hooks_dir_is_empty() {
-    for (idx, dir_path) in [
-        PathBuf::from(".cargo-husky"),
-        Path::new(".cargo-husky").join("hooks"),
-    ]
-    .iter()
-    .enumerate()
-    {
-        let root = cargo_project_for(&format!("user-hooks-dir-empty-{}", idx));
-        setup_user_hooks_feature(&root);
+fn relative_path_in_git_file() {
+    let root = cargo_project_for("gitfile-relative");
+    let gitdir = tmpdir_for("gitfile-relative-gitdir").join("repo.git");
+    fs::rename(root.join(".git"), &gitdir).unwrap();
+    // Surrounding whitespaces and CRLF are ignored
+    write!(
+        File::create(root.join(".git")).unwrap(),
+        "gitdir:  ../gitfile-relative-gitdir/repo.git \r\n"
+    )
+    .unwrap();
 
-        fs::create_dir_all(&dir_path).unwrap();
+    run_cargo(&root, ["test"]).unwrap();
 
-        assert_user_hooks_error(&root);
-    }
+    assert!(gitdir.join("hooks").join("pre-push").is_file());
 }
 
 #[test]
-#[cfg(not(target_os = "windows"))]
-fn user_hooks_dir_only_contains_non_executable_file() {
-    let root = cargo_project_for("user-hooks-dir-without-executables");
-    setup_user_hooks_feature(&root);
+fn find_git_dir_from_manifest_dir_when_out_dir_is_outside_repo() {
+    let root = cargo_project_for("out-dir-outside-repo");
+    let target_dir = tmpdir_for("out-dir-outside-repo-target");
 
-    let mut p = root.join(".cargo-husky");
-    p.push("hooks");
-    fs::create_dir_all(&p).unwrap();
-    let f1 = p.join("non-executable-file1");
-    writeln!(File::create(&f1).unwrap(), "this\nis\nnormal\ntest\nfile").unwrap();
-    assert!(f1.exists());
-    let f2 = p.join("non-executable-file2");
+    // Vendor cargo-husky in the repository. Only files needed to build it as dependency are copied
+    let vendor = root.join("vendor").join("cargo-husky");
+    fs::create_dir_all(vendor.join("src")).unwrap();
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    fs::copy(crate_dir.join("build.rs"), vendor.join("build.rs")).unwrap();
+    fs::copy(
+        crate_dir.join("src").join("lib.rs"),
+        vendor.join("src").join("lib.rs"),
+    )
+    .unwrap();
+    let manifest = fs::read_to_string(crate_dir.join("Cargo.toml")).unwrap();
+    let manifest = manifest.replace(
+        "[[test]]\nname = \"integration\"\npath = \"test/test.rs\"\n",
+        "",
+    );
+    fs::write(vendor.join("Cargo.toml"), manifest).unwrap();
+
+    let cargo_toml = fs::read_to_string(root.join("Cargo.toml")).unwrap();
+    let start = cargo_toml.find("cargo-husky = { path = ").unwrap();
+    let end = start + cargo_toml[start..].find('\n').unwrap();
+    let cargo_toml = format!(
+        "{}cargo-husky = {{ path = \"vendor/cargo-husky\" }}{}",
+        &cargo_toml[..start],
+        &cargo_toml[end..],
+    );
+    fs::write(root.join("Cargo.toml"), cargo_toml).unwrap();
+    // Metadata is read from Cargo.toml at the root of the repository where hooks are installed
     writeln!(
-        File::create(&f2).unwrap(),
-        "this\nis\nalso\nnormal\ntest\nfile"
+        open_cargo_toml(&root),
+        "\n[package.metadata.cargo-husky]\nhooks.pre-commit = [\"cargo check\"]"
     )
     .unwrap();
-    assert!(f2.exists());
 
-    assert_user_hooks_error(&root);
+    run_cargo_with_env(
+        &root,
+        ["test"],
+        &[("CARGO_TARGET_DIR", target_dir.to_str().unwrap())],
+    )
+    .unwrap();
+
+    assert!(target_dir.join("debug").is_dir());
+    let script = get_hook_script(&root, "pre-commit").unwrap();
+    assert_eq!(script.lines().filter(|l| *l == "cargo check").count(), 1);
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+}
+
+#[test]
+fn skip_path_dependency_outside_repo_when_out_dir_is_outside_repo() {
+    let root = cargo_project_for("path-dep-outside-repo");
+    let target_dir = tmpdir_for("path-dep-outside-repo-target");
+    // cargo-husky is a path dependency to this repository, which is not the repository of the project
+    let own_hook = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(".git")
+        .join("hooks")
+        .join("pre-push");
+    let own_hook_before = fs::read(&own_hook).ok();
+
+    let out = run_cargo_with_env(
+        &root,
+        ["test", "-vv"],
+        &[("CARGO_TARGET_DIR", target_dir.to_str().unwrap())],
+    )
+    .unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+
+    assert!(
+        stderr.contains("is not the repository of the crate depending on cargo-husky"),
+        "{}",
+        stderr
+    );
+    assert_eq!(get_hook_script(&root, "pre-push"), None);
+    assert_eq!(fs::read(&own_hook).ok(), own_hook_before);
+}
+
+#[test]
+fn install_hooks_to_gitdir_of_submodule() {
+    let root = cargo_project_for("submodule");
+    let superproject = tmpdir_for("submodule-super");
+    run_git(&superproject, ["init", "-q"]);
+    let module_gitdir = superproject.join(".git").join("modules").join("sub");
+    fs::create_dir_all(module_gitdir.parent().unwrap()).unwrap();
+    fs::rename(root.join(".git"), &module_gitdir).unwrap();
+    writeln!(
+        File::create(root.join(".git")).unwrap(),
+        "gitdir: {}",
+        module_gitdir.to_string_lossy()
+    )
+    .unwrap();
+
+    let out = run_cargo(&root, ["test", "-vv"]).unwrap();
+
+    assert!(module_gitdir.join("hooks").join("pre-push").is_file());
+    assert!(!superproject
+        .join(".git")
+        .join("hooks")
+        .join("pre-push")
+        .exists());
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("They are run only for Git operations within the submodule"),
+        "{}",
+        stderr
+    );
+
+    // Not warned for a repository which is not a submodule
+    let root = cargo_project_for("not-submodule");
+    let out = run_cargo(&root, ["test", "-vv"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(!stderr.contains("in a Git submodule"), "{}", stderr);
+}
+
+#[test]
+fn git_file_points_to_missing_dir() {
+    let root = cargo_project_for("gitfile-missing-target");
+    fs::remove_dir_all(root.join(".git")).unwrap();
+    writeln!(
+        File::create(root.join(".git")).unwrap(),
+        "gitdir: ../gitfile-missing-target-gitdir/worktrees/foo"
+    )
+    .unwrap();
+
+    // Build does not fail
+    let out = run_cargo(&root, ["test", "-vv"]).unwrap();
+
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("file points to") && stderr.contains("gitfile-missing-target-gitdir"),
+        "Unexpected stderr: {}",
+        stderr
+    );
 }
 
 #[test]
 #[cfg(not(target_os = "windows"))]
-fn copied_user_hooks_are_executable() {
-    use std::os::unix::fs::PermissionsExt;
+fn symlinked_git_dir() {
+    let root = cargo_project_for("symlinked-git-dir");
+    let gitdir = tmpdir_for("symlinked-git-dir-target").join("repo.git");
+    fs::rename(root.join(".git"), &gitdir).unwrap();
+    std::os::unix::fs::symlink(&gitdir, root.join(".git")).unwrap();
+    // .cargo-husky is looked up in the directory where the symbolic link is put
+    fs::create_dir(root.join(".cargo-husky")).unwrap();
+    writeln!(
+        File::create(root.join(".cargo-husky").join("config.toml")).unwrap(),
+        "hooks.pre-commit = [\"cargo test\"]"
+    )
+    .unwrap();
 
-    let root = cargo_project_for("copied-user-hooks-are-executable");
-    setup_user_hooks_feature(&root);
+    run_cargo(&root, ["test"]).unwrap();
 
-    let mut p = root.join(".cargo-husky");
+    assert!(gitdir.join("hooks").join("pre-commit").is_file());
+}
 
-    let user_hooks = TESTDIR.join("user-hooks");
-    copy_dir_recursive(&user_hooks.join(".cargo-husky"), &p);
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn broken_symlink_to_git_dir() {
+    let root = cargo_project_for("broken-symlink-to-git-dir");
+    fs::remove_dir_all(root.join(".git")).unwrap();
+    std::os::unix::fs::symlink(root.join("not-exist"), root.join(".git")).unwrap();
 
-    p.push("hooks");
-    p.push("non-executable-file.txt");
-    writeln!(File::create(p).unwrap(), "foo\nbar\npiyo").unwrap();
+    let out = run_cargo(&root, ["test", "-vv"]).unwrap();
 
-    run_cargo(&root, &["test"]).unwrap();
+    let stderr = str::from_utf8(out.stderr.as_slice()).unwrap();
+    assert!(
+        stderr.contains("is a symbolic link to a directory which does not exist"),
+        "Unexpected stderr: {}",
+        stderr
+    );
+    assert!(!root.join("not-exist").exists());
+}
 
-    for name in &["pre-commit", "post-merge"] {
-        let hook = File::open(hook_path(&root, name)).unwrap();
-        let mode = hook.metadata().unwrap().permissions().mode();
-        assert_eq!(mode & 0o555, 0o555);
-    }
+#[test]
+fn install_hooks_to_common_dir_of_worktree() {
+    let root = cargo_project_for("worktree");
+    let main_gitdir = tmpdir_for("worktree-main").join(".git");
+    let worktree_gitdir = main_gitdir.join("worktrees").join("foo");
+    fs::create_dir_all(&worktree_gitdir).unwrap();
+    fs::create_dir_all(main_gitdir.join("hooks")).unwrap();
 
-    assert!(!hook_path(&root, "non-executable-file.txt").exists());
+    fs::remove_dir_all(root.join(".git")).unwrap();
+    writeln!(
+        File::create(root.join(".git")).unwrap(),
+        "gitdir: {}",
+        worktree_gitdir.to_string_lossy()
+    )
+    .unwrap();
+
+    run_cargo(&root, ["test"]).unwrap();
+
+    assert!(main_gitdir.join("hooks").join("pre-push").is_file());
+    assert!(!worktree_gitdir.join("hooks").join("pre-push").exists());
 }
 
 #[test]
-fn empty_script_file_not_allowed() {
-    let root = cargo_project_for("empty-user-hook");
-    setup_user_hooks_feature(&root);
+#[cfg(not(target_os = "windows"))]
+fn install_hooks_to_each_worktree() {
+    let root = cargo_project_for("worktree-hooks");
+    writeln!(open_cargo_toml(&root), "features = [\"worktree-hooks\"]").unwrap();
+    set_git_config(&root, "user.name", "cargo-husky");
+    set_git_config(&root, "user.email", "cargo-husky@example.com");
+    run_git(&root, ["commit", "-q", "--allow-empty", "-m", "initial"]);
+    let worktree = tmpdir_for("worktree-hooks-wt").join("wt");
+    run_git(
+        &root,
+        [
+            "worktree",
+            "add",
+            "-q",
+            "-b",
+            "wt",
+            worktree.to_str().unwrap(),
+        ],
+    );
 
-    let user_hooks = TESTDIR.join("empty-user-hook");
-    copy_dir_recursive(&user_hooks.join(".cargo-husky"), &root.join(".cargo-husky"));
+    run_cargo(&root, ["test"]).unwrap();
 
-    let err = run_cargo(&root, &["test"]).unwrap_err();
-    assert!(format!("{}", err).contains("User hook script is empty"));
+    assert!(get_hook_script(&root, "pre-push").is_some());
+    let hooks_dir = fs::canonicalize(root.join(".git"))
+        .unwrap()
+        .join("worktrees")
+        .join("wt")
+        .join("hooks");
+    assert!(hooks_dir.join("pre-push").is_file());
+
+    let git_config = |dir: &Path| {
+        let out = Command::new("git")
+            .args(["config", "--get", "core.hooksPath"])
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap().trim().to_string()
+    };
+    // Only the linked worktree uses its own hooks
+    assert_eq!(git_config(&worktree), hooks_dir.to_string_lossy());
+    assert_eq!(git_config(&root), "");
 }